@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use rocket::async_trait;
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+	character_sheet::{AbilityScore, CharacterSheet, CharacterSource, ABILITIES},
+	CharacterId,
+};
+
+/// Skill names, their identifiers in the character JSON and the index of their ability
+const SKILLS: [(&str, &str, usize); 18] = [
+	("Acrobatics", "acrobatics", 1),
+	("Animal Handling", "animal-handling", 4),
+	("Arcana", "arcana", 3),
+	("Athletics", "athletics", 0),
+	("Deception", "deception", 5),
+	("History", "history", 3),
+	("Insight", "insight", 4),
+	("Intimidation", "intimidation", 5),
+	("Investigation", "investigation", 3),
+	("Medicine", "medicine", 4),
+	("Nature", "nature", 3),
+	("Perception", "perception", 4),
+	("Performance", "performance", 5),
+	("Persuasion", "persuasion", 5),
+	("Religion", "religion", 3),
+	("Sleight of Hand", "sleight-of-hand", 1),
+	("Stealth", "stealth", 1),
+	("Survival", "survival", 4),
+];
+
+#[derive(Deserialize)]
+struct CharacterResponse {
+	success: bool,
+	message: Option<String>,
+	data: Option<Character>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Character {
+	name: String,
+	stats: Vec<Stat>,
+	bonus_stats: Vec<Stat>,
+	override_stats: Vec<Stat>,
+	base_hit_points: i32,
+	bonus_hit_points: Option<i32>,
+	override_hit_points: Option<i32>,
+	classes: Vec<Class>,
+	/// Modifiers grouped by where they come from: race, class, background, item, feat...
+	modifiers: HashMap<String, Vec<Modifier>>,
+	#[serde(default)]
+	inventory: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct Stat {
+	id: usize,
+	value: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct Class {
+	level: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Modifier {
+	#[serde(rename = "type")]
+	kind: String,
+	sub_type: String,
+	value: Option<i32>,
+	component_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+	equipped: bool,
+	#[serde(default)]
+	is_attuned: bool,
+	definition: ItemDefinition,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ItemDefinition {
+	id: i64,
+	#[serde(default)]
+	can_attune: bool,
+}
+
+/// Finds the value of the stat with the given 1-based id
+fn stat(stats: &[Stat], id: usize) -> Option<i32> {
+	stats
+		.iter()
+		.find(|stat| stat.id == id)
+		.and_then(|stat| stat.value)
+}
+
+impl Character {
+	/// Modifiers that currently apply: items only count while equipped and, if needed, attuned
+	fn active_modifiers(&self) -> Vec<&Modifier> {
+		self.modifiers
+			.iter()
+			.flat_map(|(source, modifiers)| {
+				modifiers.iter().filter(move |modifier| {
+					source != "item"
+						|| self.inventory.iter().any(|item| {
+							Some(item.definition.id) == modifier.component_id
+								&& item.equipped && (!item.definition.can_attune || item.is_attuned)
+						})
+				})
+			})
+			.collect()
+	}
+
+	fn level(&self) -> i32 {
+		self.classes
+			.iter()
+			.map(|class| class.level)
+			.sum::<i32>()
+			.max(1)
+	}
+
+	fn proficiency_bonus(&self) -> i32 {
+		2 + (self.level() - 1) / 4
+	}
+
+	/// Rolled or fixed hit points plus the Constitution modifier and bonuses such as Tough for every level
+	fn max_hit_points(&self, modifiers: &[&Modifier], constitution: AbilityScore) -> i32 {
+		if let Some(hit_points) = self.override_hit_points {
+			return hit_points;
+		}
+		let per_level = constitution.modifier + sum(modifiers, "bonus", "hit-points-per-level");
+		self.base_hit_points + self.bonus_hit_points.unwrap_or(0) + per_level * self.level()
+	}
+
+	fn ability_scores(&self, modifiers: &[&Modifier]) -> Vec<AbilityScore> {
+		ABILITIES
+			.iter()
+			.enumerate()
+			.map(|(i, (name, _))| {
+				let id = i + 1;
+				if let Some(score) = stat(&self.override_stats, id) {
+					return AbilityScore::new(score);
+				}
+				let sub_type = format!("{}-score", name.to_lowercase());
+				let score = stat(&self.stats, id).unwrap_or(10)
+					+ stat(&self.bonus_stats, id).unwrap_or(0)
+					+ sum(modifiers, "bonus", &sub_type);
+				// Items such as the Gauntlets of Ogre Power set a minimum score
+				let set = modifiers
+					.iter()
+					.filter(|modifier| modifier.kind == "set" && modifier.sub_type == sub_type)
+					.filter_map(|modifier| modifier.value)
+					.max()
+					.unwrap_or(0);
+				AbilityScore::new(score.max(set))
+			})
+			.collect()
+	}
+}
+
+fn has(modifiers: &[&Modifier], kind: &str, sub_type: &str) -> bool {
+	modifiers
+		.iter()
+		.any(|modifier| modifier.kind == kind && modifier.sub_type == sub_type)
+}
+
+fn sum(modifiers: &[&Modifier], kind: &str, sub_type: &str) -> i32 {
+	modifiers
+		.iter()
+		.filter(|modifier| modifier.kind == kind && modifier.sub_type == sub_type)
+		.filter_map(|modifier| modifier.value)
+		.sum()
+}
+
+impl From<Character> for CharacterSheet {
+	fn from(character: Character) -> Self {
+		let modifiers = character.active_modifiers();
+		let modifiers = modifiers.as_slice();
+		let proficiency_bonus = character.proficiency_bonus();
+		let ability_scores = character.ability_scores(modifiers);
+
+		let skills = SKILLS
+			.iter()
+			.map(|(name, sub_type, ability)| {
+				let proficiency = if has(modifiers, "expertise", sub_type) {
+					2 * proficiency_bonus
+				} else if has(modifiers, "proficiency", sub_type) {
+					proficiency_bonus
+				} else if has(modifiers, "half-proficiency", sub_type)
+					|| has(modifiers, "half-proficiency", "ability-checks")
+				{
+					proficiency_bonus / 2
+				} else {
+					0
+				};
+				let modifier = ability_scores[*ability].modifier
+					+ proficiency + sum(modifiers, "bonus", sub_type)
+					+ sum(modifiers, "bonus", "ability-checks");
+				((*name).to_owned(), modifier)
+			})
+			.collect();
+
+		let saving_throws = ABILITIES
+			.iter()
+			.zip(&ability_scores)
+			.map(|((name, _), ability_score)| {
+				let sub_type = format!("{}-saving-throws", name.to_lowercase());
+				let proficiency = if has(modifiers, "proficiency", &sub_type) {
+					proficiency_bonus
+				} else {
+					0
+				};
+				let modifier = ability_score.modifier
+					+ proficiency + sum(modifiers, "bonus", &sub_type)
+					+ sum(modifiers, "bonus", "saving-throws");
+				((*name).to_owned(), modifier)
+			})
+			.collect();
+
+		let initiative = ability_scores[1].modifier
+			+ if has(modifiers, "half-proficiency", "initiative")
+				|| has(modifiers, "half-proficiency", "ability-checks")
+			{
+				proficiency_bonus / 2
+			} else {
+				0
+			} + sum(modifiers, "bonus", "initiative")
+			// Initiative is a Dexterity check, so bonuses to all ability checks apply
+			+ sum(modifiers, "bonus", "ability-checks");
+
+		let max_hit_points = character.max_hit_points(modifiers, ability_scores[2]);
+
+		let abilities = ABILITIES
+			.iter()
+			.zip(ability_scores)
+			.map(|((name, _), ability_score)| ((*name).to_owned(), ability_score))
+			.collect();
+
+		CharacterSheet {
+			name: character.name,
+			skills,
+			saving_throws,
+			abilities,
+			initiative,
+			max_hit_points,
+			// Attack bonuses depend on weapon properties and proficiencies that are not computed yet
+			attacks: Vec::new(),
+		}
+	}
+}
+
+fn parse_character_response(text: &str) -> anyhow::Result<CharacterSheet> {
+	let response: CharacterResponse = serde_json::from_str(text)?;
+	match response {
+		CharacterResponse {
+			success: true,
+			data: Some(character),
+			..
+		} => Ok(character.into()),
+		CharacterResponse { message, .. } => Err(anyhow!(
+			"Character service error: {}",
+			message.unwrap_or_default()
+		)),
+	}
+}
+
+/// Reads characters from the JSON endpoint that D&D Beyond's own character sheet uses
+#[derive(Clone, Debug)]
+pub struct CharacterService {
+	service_url: Url,
+}
+
+impl CharacterService {
+	pub fn new(service_url: &str) -> Result<CharacterService, url::ParseError> {
+		// Joining the character ID replaces the last path segment unless the URL ends with a slash
+		let service_url = format!("{}/", service_url.trim_end_matches('/')).parse()?;
+		Ok(CharacterService { service_url })
+	}
+
+	fn character_url(&self, character_id: CharacterId) -> Result<Url, url::ParseError> {
+		self.service_url.join(&character_id.to_string())
+	}
+}
+
+#[async_trait]
+impl CharacterSource for CharacterService {
+	async fn fetch(&self, character_id: CharacterId) -> anyhow::Result<CharacterSheet> {
+		let url = self.character_url(character_id)?;
+		let text = reqwest::get(url).await?.error_for_status()?.text().await?;
+		parse_character_response(&text)
+	}
+
+	fn reads_attacks(&self) -> bool {
+		false
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_character_response, CharacterService};
+	use crate::CharacterId;
+
+	#[test]
+	fn compute_character_sheet() {
+		let text = include_str!("../fixtures/character_service.json");
+		let character_sheet = parse_character_response(text).unwrap();
+
+		assert_eq!(character_sheet.name, "Sample Character");
+		assert_eq!(character_sheet.abilities["Dexterity"].score, 17);
+		assert_eq!(character_sheet.abilities["Strength"].score, 19);
+		assert_eq!(character_sheet.abilities["Charisma"].modifier, -1);
+
+		// proficient, level 5, +1 from the luckstone
+		assert_eq!(character_sheet.skills["Athletics"], 8);
+		// expertise
+		assert_eq!(character_sheet.skills["Stealth"], 10);
+		// unequipped cloak gives no bonus, only the luckstone does
+		assert_eq!(character_sheet.skills["Arcana"], 1);
+		assert_eq!(character_sheet.saving_throws["Dexterity"], 7);
+		assert_eq!(character_sheet.saving_throws["Wisdom"], 2);
+		// a Dexterity check, so the luckstone counts
+		assert_eq!(character_sheet.initiative, 4);
+		assert_eq!(character_sheet.max_hit_points, 48);
+	}
+
+	#[test]
+	fn report_private_character() {
+		let text = r#"{"success":false,"message":"Character is private","data":null}"#;
+		assert!(parse_character_response(text).is_err());
+	}
+
+	#[test]
+	fn append_character_id_to_service_url() {
+		for url in &[
+			"https://example.com/character/v5/character",
+			"https://example.com/character/v5/character/",
+		] {
+			let service = CharacterService::new(url).unwrap();
+			assert_eq!(
+				service
+					.character_url(CharacterId(36535842))
+					.unwrap()
+					.as_str(),
+				"https://example.com/character/v5/character/36535842"
+			);
+		}
+	}
+}
@@ -1,13 +1,162 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Debug};
 
 use anyhow::anyhow;
 use failure::{err_msg, Fallible};
-use headless_chrome::{protocol::target::methods::CreateTarget, Browser};
-use rocket::tokio;
+use headless_chrome::{protocol::target::methods::CreateTarget, Browser, Element};
+use rocket::{async_trait, tokio};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use url::Url;
 
+use crate::{dice::DiceExpression, CharacterId};
+
+/// Full names and abbreviations of the six abilities
+pub const ABILITIES: [(&str, &str); 6] = [
+	("Strength", "STR"),
+	("Dexterity", "DEX"),
+	("Constitution", "CON"),
+	("Intelligence", "INT"),
+	("Wisdom", "WIS"),
+	("Charisma", "CHA"),
+];
+
+/// Expands an ability abbreviation such as "dex" into its full name
+pub fn ability_name(abbreviation: &str) -> Option<&'static str> {
+	ABILITIES
+		.iter()
+		.find(|(_, short)| short.eq_ignore_ascii_case(abbreviation))
+		.map(|(name, _)| *name)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbilityScore {
+	pub score: i32,
+	pub modifier: i32,
+}
+
+impl AbilityScore {
+	pub fn new(score: i32) -> AbilityScore {
+		AbilityScore {
+			score,
+			// round down, including for negative modifiers
+			modifier: (score - 10).div_euclid(2),
+		}
+	}
+}
+
+/// A row of the attacks table. Spells that call for a saving throw have no to-hit bonus.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attack {
+	pub name: String,
+	pub to_hit: Option<i32>,
+	pub damage: Option<DiceExpression>,
+	pub damage_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct CharacterSheet {
+	pub name: String,
 	pub skills: HashMap<String, i32>,
+	pub saving_throws: HashMap<String, i32>,
+	pub abilities: HashMap<String, AbilityScore>,
+	pub initiative: i32,
+	pub max_hit_points: i32,
+	pub attacks: Vec<Attack>,
+}
+
+/// Calls a JS function that returns a string and strips the quotes around it
+fn call_js_fn_text(element: &Element, js_fn: &str) -> Fallible<String> {
+	Ok(element
+		.call_js_fn(js_fn, true)?
+		.value
+		.ok_or_else(|| err_msg("Function did not return a value"))?
+		.to_string()
+		.replace("\"", ""))
+}
+
+/// Somewhere to get character sheets from, such as a headless browser or a web API
+#[async_trait]
+pub trait CharacterSource: Debug + Send + Sync {
+	async fn fetch(&self, character_id: CharacterId) -> anyhow::Result<CharacterSheet>;
+
+	/// Whether the sheets come with the attacks table
+	fn reads_attacks(&self) -> bool {
+		true
+	}
+}
+
+fn character_sheet_url(character_id: CharacterId) -> Url {
+	let base = Url::parse("https://www.dndbeyond.com/characters/").unwrap();
+	base.join(&character_id.to_string()).unwrap()
+}
+
+/// Calls a JS function that returns a JSON string and deserializes it
+fn call_js_fn_json<T: DeserializeOwned>(element: &Element, js_fn: &str) -> Fallible<T> {
+	let value = element
+		.call_js_fn(js_fn, true)?
+		.value
+		.ok_or_else(|| err_msg("Function did not return a value"))?;
+	let text = value
+		.as_str()
+		.ok_or_else(|| err_msg("Function did not return a string"))?;
+	Ok(serde_json::from_str(text)?)
+}
+
+/// Calls a JS function that returns "name,modifier;name,modifier;..." and parses the result
+fn parse_modifiers(element: &Element, js_fn: &str) -> Fallible<HashMap<String, i32>> {
+	call_js_fn_text(element, js_fn)?
+		.split(';')
+		.map(
+			|s| match s.split(',').take(2).collect::<Vec<&str>>().as_slice() {
+				[a, b, ..] => Ok(((*a).to_owned(), b.parse::<i32>()?)),
+				_ => {
+					let message = format!("Cannot parse string \"{}\" into name and modifier", s);
+					Err(err_msg(message))
+				}
+			},
+		)
+		.collect()
+}
+
+/// Parses "abbreviation,primary,secondary;..." where one of the values is the signed modifier
+/// and the other is the score, depending on the sheet's display settings
+fn parse_ability_scores(text: &str) -> Fallible<HashMap<String, AbilityScore>> {
+	text.split(';')
+		.map(|s| match s.split(',').collect::<Vec<&str>>().as_slice() {
+			[abbreviation, primary, secondary] => {
+				let name = ability_name(abbreviation)
+					.ok_or_else(|| err_msg(format!("Unknown ability \"{}\"", abbreviation)))?;
+				let score = if primary.starts_with(&['+', '-'][..]) {
+					secondary
+				} else {
+					primary
+				};
+				Ok((name.to_owned(), AbilityScore::new(score.parse()?)))
+			}
+			_ => {
+				let message = format!("Cannot parse string \"{}\" into ability score", s);
+				Err(err_msg(message))
+			}
+		})
+		.collect()
+}
+
+#[derive(Deserialize)]
+struct AttackRow {
+	name: String,
+	to_hit: Option<String>,
+	damage: Option<String>,
+	damage_type: Option<String>,
+}
+
+impl From<AttackRow> for Attack {
+	fn from(row: AttackRow) -> Self {
+		Attack {
+			name: row.name,
+			to_hit: row.to_hit.and_then(|to_hit| to_hit.parse().ok()),
+			damage: row.damage.and_then(|damage| damage.parse().ok()),
+			damage_type: row.damage_type,
+		}
+	}
 }
 
 #[derive(Clone, Debug)]
@@ -35,9 +184,9 @@ impl Headless {
 		)?;
 
 		// Parse the skill list
-		let skills = element
-			.call_js_fn(
-				r#"
+		let skills = parse_modifiers(
+			&element,
+			r#"
 			function() {
 				const items = this.querySelectorAll(".ct-skills__item");
 				const skillValues = [...items].map(item => {
@@ -50,26 +199,116 @@ impl Headless {
 					.join(";");
 				return text;
 			}"#,
-				true,
+		)?;
+
+		// Parse the saving throws, which are listed by ability abbreviation
+		let element = tab.find_element(".ct-saving-throws")?;
+		let saving_throws = parse_modifiers(
+			&element,
+			r#"
+			function() {
+				const items = this.querySelectorAll(".ct-saving-throws-summary__ability");
+				const saveValues = [...items].map(item => {
+					const ability = item.querySelector(".ct-saving-throws-summary__ability-name");
+					const modifier = item.querySelector(".ct-saving-throws-summary__ability-modifier");
+					return [ability, modifier];
+				});
+				const text = saveValues
+					.map(([ability, modifier]) => `${ability.innerText},${modifier.innerText.replace("\n", "")}`)
+					.join(";");
+				return text;
+			}"#,
+		)?
+		.into_iter()
+		.map(|(ability, modifier)| match ability_name(&ability) {
+			Some(name) => Ok((name.to_owned(), modifier)),
+			None => Err(err_msg(format!("Unknown ability \"{}\"", ability))),
+		})
+		.collect::<Fallible<HashMap<String, i32>>>()?;
+
+		// Parse the ability scores
+		let element = tab.find_element(".ct-quick-info__abilities")?;
+		let abilities = parse_ability_scores(&call_js_fn_text(
+			&element,
+			r#"
+			function() {
+				const items = this.querySelectorAll(".ddbc-ability-summary");
+				const abilityValues = [...items].map(item => {
+					const ability = item.querySelector(".ddbc-ability-summary__abbr");
+					const primary = item.querySelector(".ddbc-ability-summary__primary");
+					const secondary = item.querySelector(".ddbc-ability-summary__secondary");
+					return [ability, primary, secondary];
+				});
+				const text = abilityValues
+					.map(values => values.map(value => value.innerText.replace("\n", "")).join(","))
+					.join(";");
+				return text;
+			}"#,
+		)?)?;
+
+		// Parse the attacks table. It is missing from the page when there is nothing to attack with.
+		let attacks = match tab.find_element(".ct-attack-table") {
+			Ok(element) => call_js_fn_json::<Vec<AttackRow>>(
+				&element,
+				r#"
+			function() {
+				const rows = this.querySelectorAll(".ddbc-combat-attack");
+				const attacks = [...rows].map(row => {
+					const text = selector => {
+						const element = row.querySelector(selector);
+						return element ? element.innerText.replace(/\s/g, "") : null;
+					};
+					const damageType = row.querySelector(".ddbc-combat-attack__damage .ddbc-tooltip");
+					return {
+						name: row.querySelector(".ddbc-combat-attack__label").innerText,
+						to_hit: text(".ddbc-combat-attack__tohit"),
+						damage: text(".ddbc-combat-attack__damage .ddbc-damage__value"),
+						damage_type: damageType ? damageType.getAttribute("data-original-title") : null,
+					};
+				});
+				return JSON.stringify(attacks);
+			}"#,
 			)?
-			.value
-			.ok_or_else(|| err_msg("Function did not return a value"))?
-			.to_string()
-			.replace("\"", "")
-			.split(';')
-			.map(
-				|s| match s.split(',').take(2).collect::<Vec<&str>>().as_slice() {
-					[a, b, ..] => Ok(((*a).to_owned(), b.parse::<i32>()?)),
-					_ => {
-						let message =
-							format!("Cannot parse string \"{}\" into skill name and modifier", s);
-						Err(err_msg(message))
-					}
-				},
-			)
-			.collect::<Fallible<HashMap<String, i32>>>()?;
-
-		Ok(CharacterSheet { skills })
+			.into_iter()
+			.map(Attack::from)
+			.collect(),
+			Err(_) => Vec::new(),
+		};
+
+		let element = tab.find_element(".ddbc-character-name")?;
+		let name = call_js_fn_text(&element, "function() { return this.innerText; }")?;
+
+		let element = tab.find_element(".ct-initiative-box__value")?;
+		let initiative = call_js_fn_text(
+			&element,
+			r#"function() { return this.innerText.replace("\n", ""); }"#,
+		)?
+		.parse()?;
+
+		let element = tab.find_element(".ct-health-summary")?;
+		let max_hit_points = call_js_fn_text(
+			&element,
+			r#"
+			function() {
+				const items = this.querySelectorAll(".ct-health-summary__hp-item");
+				const max = [...items].find(item => {
+					const label = item.querySelector(".ct-health-summary__hp-item-label");
+					return label && label.innerText.trim().toLowerCase() === "max";
+				});
+				return max.querySelector(".ct-health-summary__hp-number").innerText;
+			}"#,
+		)?
+		.parse()?;
+
+		Ok(CharacterSheet {
+			name,
+			skills,
+			saving_throws,
+			abilities,
+			initiative,
+			max_hit_points,
+			attacks,
+		})
 	}
 
 	pub async fn download_character_sheet(
@@ -87,3 +326,51 @@ impl Headless {
 		Ok(character_sheet)
 	}
 }
+
+#[async_trait]
+impl CharacterSource for Headless {
+	async fn fetch(&self, character_id: CharacterId) -> anyhow::Result<CharacterSheet> {
+		self.download_character_sheet(character_sheet_url(character_id))
+			.await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_ability_scores, AbilityScore, Attack, AttackRow};
+
+	#[test]
+	fn ability_modifier_rounds_down() {
+		assert_eq!(AbilityScore::new(10).modifier, 0);
+		assert_eq!(AbilityScore::new(15).modifier, 2);
+		assert_eq!(AbilityScore::new(9).modifier, -1);
+		assert_eq!(AbilityScore::new(1).modifier, -5);
+	}
+
+	#[test]
+	fn parse_ability_scores_in_either_order() {
+		let abilities = parse_ability_scores("STR,+3,16;dex,14,+2").unwrap();
+		assert_eq!(abilities["Strength"], AbilityScore::new(16));
+		assert_eq!(abilities["Dexterity"].modifier, 2);
+	}
+
+	#[test]
+	fn parse_attack_row() {
+		let attack = Attack::from(AttackRow {
+			name: "Longsword".to_string(),
+			to_hit: Some("+5".to_string()),
+			damage: Some("1d8+3".to_string()),
+			damage_type: Some("Slashing".to_string()),
+		});
+		assert_eq!(attack.to_hit, Some(5));
+		assert_eq!(attack.damage.unwrap().to_string(), "1d8+3");
+
+		let attack = Attack::from(AttackRow {
+			name: "Sacred Flame".to_string(),
+			to_hit: Some("DC13".to_string()),
+			damage: None,
+			damage_type: None,
+		});
+		assert_eq!(attack.to_hit, None);
+	}
+}
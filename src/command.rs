@@ -0,0 +1,183 @@
+use std::fmt::Display;
+
+use telegram_bot::{MessageEntity, MessageEntityKind};
+
+/// A bot command at the start of a message, such as "/skill@ligmir_bot arcana adv"
+#[derive(Debug, PartialEq, Eq)]
+pub struct Command<'a> {
+	/// The command without the slash or the bot username, in lowercase
+	pub name: String,
+	/// The bot the command was addressed to, if any
+	pub bot: Option<&'a str>,
+	/// Everything after the command
+	pub args: &'a str,
+}
+
+impl<'a> Command<'a> {
+	/// Finds the command in a message using the entities Telegram marked in it.
+	/// Only a command at the very start of the message counts, and commands addressed
+	/// to other bots, such as "/roll@other_bot", are left to them.
+	/// Without the bot's username every command is taken.
+	pub fn parse(
+		text: &'a str,
+		entities: &[MessageEntity],
+		bot_username: Option<&str>,
+	) -> Option<Command<'a>> {
+		let entity = entities
+			.iter()
+			.find(|entity| entity.kind == MessageEntityKind::BotCommand && entity.offset == 0)?;
+		let end = utf16_to_byte_offset(text, entity.length as usize)?;
+		let mut command = text[..end].trim_start_matches('/').splitn(2, '@');
+		let name = command.next().unwrap_or_default().to_lowercase();
+		let bot = command.next();
+		if let (Some(bot), Some(username)) = (bot, bot_username) {
+			// Usernames are case-insensitive
+			if !bot.eq_ignore_ascii_case(username) {
+				return None;
+			}
+		}
+		Some(Command {
+			name,
+			bot,
+			args: text[end..].trim(),
+		})
+	}
+
+	/// The arguments of a command that doesn't work without them
+	pub fn required_args(&self, usage: &'static str) -> Result<&'a str, MissingArguments> {
+		self.required(self.args, usage)
+	}
+
+	/// Checks that something is left of the arguments after options such as "adv" or "dc15"
+	/// have been split off
+	pub fn required<'b>(
+		&self,
+		arg: &'b str,
+		usage: &'static str,
+	) -> Result<&'b str, MissingArguments> {
+		if arg.is_empty() {
+			Err(MissingArguments {
+				command: self.name.clone(),
+				usage,
+			})
+		} else {
+			Ok(arg)
+		}
+	}
+}
+
+/// Telegram measures entities in UTF-16 code units
+fn utf16_to_byte_offset(text: &str, offset: usize) -> Option<usize> {
+	let mut utf16_offset = 0;
+	for (byte_offset, c) in text.char_indices() {
+		if utf16_offset == offset {
+			return Some(byte_offset);
+		}
+		utf16_offset += c.len_utf16();
+	}
+	if utf16_offset == offset {
+		Some(text.len())
+	} else {
+		None
+	}
+}
+
+/// A command was sent without the arguments it needs
+#[derive(Debug, PartialEq, Eq)]
+pub struct MissingArguments {
+	pub command: String,
+	/// An example of how to use the command
+	pub usage: &'static str,
+}
+
+impl Display for MissingArguments {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"/{} needs more than that, like {}",
+			self.command, self.usage
+		)
+	}
+}
+
+impl std::error::Error for MissingArguments {}
+
+#[cfg(test)]
+mod tests {
+	use super::{Command, MissingArguments};
+	use telegram_bot::{MessageEntity, MessageEntityKind};
+
+	fn command_entity(length: i64) -> Vec<MessageEntity> {
+		vec![MessageEntity {
+			offset: 0,
+			length,
+			kind: MessageEntityKind::BotCommand,
+		}]
+	}
+
+	#[test]
+	fn parse_command_addressed_to_bot() {
+		let text = "/skill@ligmir_bot arcana adv";
+		assert_eq!(
+			Command::parse(text, &command_entity(17), None),
+			Some(Command {
+				name: "skill".to_string(),
+				bot: Some("ligmir_bot"),
+				args: "arcana adv",
+			})
+		);
+	}
+
+	#[test]
+	fn ignore_commands_for_other_bots() {
+		let text = "/skill@Ligmir_Bot arcana";
+		let command = Command::parse(text, &command_entity(17), Some("ligmir_bot")).unwrap();
+		assert_eq!(command.bot, Some("Ligmir_Bot"));
+		assert_eq!(
+			Command::parse(
+				"/skill@other_bot arcana",
+				&command_entity(16),
+				Some("ligmir_bot")
+			),
+			None
+		);
+		let command = Command::parse("/skill arcana", &command_entity(6), Some("ligmir_bot"));
+		assert_eq!(command.unwrap().args, "arcana");
+	}
+
+	#[test]
+	fn parse_bare_command() {
+		let command = Command::parse("/skill", &command_entity(6), None).unwrap();
+		assert_eq!(command.name, "skill");
+		assert_eq!(command.args, "");
+		assert_eq!(
+			command.required_args("/skill stealth"),
+			Err(MissingArguments {
+				command: "skill".to_string(),
+				usage: "/skill stealth",
+			})
+		);
+
+		let command = Command::parse("/skillz stealth", &command_entity(7), None).unwrap();
+		assert_eq!(command.name, "skillz");
+	}
+
+	#[test]
+	fn ignore_text_without_command() {
+		assert_eq!(Command::parse("/skill stealth", &[], None), None);
+		let mention = vec![MessageEntity {
+			offset: 0,
+			length: 6,
+			kind: MessageEntityKind::Mention,
+		}];
+		assert_eq!(Command::parse("@anna hi", &mention, None), None);
+	}
+
+	#[test]
+	fn count_offsets_in_utf16() {
+		let command = Command::parse("/roll 🎲 d20", &command_entity(5), None).unwrap();
+		assert_eq!(command.args, "🎲 d20");
+		// An entity that ends in the middle of a character is not a command
+		assert_eq!(Command::parse("/🎲", &command_entity(2), None), None);
+	}
+}
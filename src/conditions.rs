@@ -0,0 +1,217 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::redis_json::redis_json;
+
+/// The conditions from appendix A of the Player's Handbook
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Condition {
+	Blinded,
+	Charmed,
+	Deafened,
+	/// Exhaustion comes in levels from 1 to 6
+	Exhaustion(u32),
+	Frightened,
+	Grappled,
+	Incapacitated,
+	Invisible,
+	Paralyzed,
+	Petrified,
+	Poisoned,
+	Prone,
+	Restrained,
+	Stunned,
+	Unconscious,
+}
+
+const CONDITIONS: [(&str, Condition); 15] = [
+	("blinded", Condition::Blinded),
+	("charmed", Condition::Charmed),
+	("deafened", Condition::Deafened),
+	("exhaustion", Condition::Exhaustion(1)),
+	("frightened", Condition::Frightened),
+	("grappled", Condition::Grappled),
+	("incapacitated", Condition::Incapacitated),
+	("invisible", Condition::Invisible),
+	("paralyzed", Condition::Paralyzed),
+	("petrified", Condition::Petrified),
+	("poisoned", Condition::Poisoned),
+	("prone", Condition::Prone),
+	("restrained", Condition::Restrained),
+	("stunned", Condition::Stunned),
+	("unconscious", Condition::Unconscious),
+];
+
+impl Condition {
+	fn name(self) -> &'static str {
+		CONDITIONS
+			.iter()
+			.find(|(_, condition)| condition.is_same_kind(self))
+			.map(|(name, _)| *name)
+			.expect("Every condition has a name")
+	}
+
+	/// Compares conditions ignoring the level of exhaustion
+	fn is_same_kind(self, other: Condition) -> bool {
+		std::mem::discriminant(&self) == std::mem::discriminant(&other)
+	}
+}
+
+/// Parses a condition name such as "poisoned" or "exhaustion 2"
+impl FromStr for Condition {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim().to_lowercase();
+		let mut words = s.split_whitespace();
+		let name = words.next().unwrap_or_default();
+		let level = words.next();
+		match (CONDITIONS.iter().find(|(other, _)| *other == name), level) {
+			(Some((_, Condition::Exhaustion(_))), Some(level)) => match level.parse() {
+				Ok(level) if (1..=6).contains(&level) => Ok(Condition::Exhaustion(level)),
+				_ => Err(anyhow!("Exhaustion levels go from 1 to 6.")),
+			},
+			(Some((_, condition)), None) => Ok(*condition),
+			_ => Err(anyhow!("Unknown condition \"{}\".", s)),
+		}
+	}
+}
+
+impl Display for Condition {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Condition::Exhaustion(level) => write!(f, "exhaustion {}", level),
+			condition => f.write_str(condition.name()),
+		}
+	}
+}
+
+/// Conditions currently affecting a character
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Conditions {
+	pub conditions: Vec<Condition>,
+}
+
+impl Conditions {
+	/// Adds the condition. Adding exhaustion again replaces its level.
+	pub fn add(&mut self, condition: Condition) {
+		self.remove(condition);
+		self.conditions.push(condition);
+	}
+
+	/// Removes the condition, returning whether the character had it
+	pub fn remove(&mut self, condition: Condition) -> bool {
+		let len = self.conditions.len();
+		self.conditions
+			.retain(|other| !other.is_same_kind(condition));
+		self.conditions.len() != len
+	}
+
+	fn find(&self, predicate: impl Fn(Condition) -> bool) -> Option<Condition> {
+		self.conditions
+			.iter()
+			.copied()
+			.find(|condition| predicate(*condition))
+	}
+
+	/// The condition that gives disadvantage on ability checks, including skill checks and initiative
+	pub fn check_disadvantage(&self) -> Option<Condition> {
+		self.find(|condition| {
+			matches!(
+				condition,
+				Condition::Poisoned | Condition::Frightened | Condition::Exhaustion(_)
+			)
+		})
+	}
+
+	/// The condition that gives disadvantage on the character's own attack rolls
+	pub fn attack_disadvantage(&self) -> Option<Condition> {
+		self.find(|condition| match condition {
+			Condition::Blinded
+			| Condition::Frightened
+			| Condition::Poisoned
+			| Condition::Prone
+			| Condition::Restrained => true,
+			Condition::Exhaustion(level) => level >= 3,
+			_ => false,
+		})
+	}
+
+	/// The condition that gives advantage on the character's own attack rolls
+	pub fn attack_advantage(&self) -> Option<Condition> {
+		self.find(|condition| condition == Condition::Invisible)
+	}
+
+	/// The condition that gives disadvantage on saving throws of the given ability
+	pub fn saving_throw_disadvantage(&self, ability: &str) -> Option<Condition> {
+		self.find(|condition| match condition {
+			Condition::Restrained => ability == "Dexterity",
+			Condition::Exhaustion(level) => level >= 3,
+			_ => false,
+		})
+	}
+}
+
+impl Display for Conditions {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let names: Vec<String> = self
+			.conditions
+			.iter()
+			.map(|condition| condition.to_string())
+			.collect();
+		f.write_str(&names.join(", "))
+	}
+}
+
+redis_json!(Conditions);
+
+#[cfg(test)]
+mod tests {
+	use super::{Condition, Conditions};
+
+	#[test]
+	fn parse_conditions() {
+		assert_eq!(
+			"Poisoned".parse::<Condition>().unwrap(),
+			Condition::Poisoned
+		);
+		assert_eq!(
+			"exhaustion".parse::<Condition>().unwrap(),
+			Condition::Exhaustion(1)
+		);
+		assert_eq!(
+			"exhaustion 3".parse::<Condition>().unwrap(),
+			Condition::Exhaustion(3)
+		);
+		assert!("exhaustion 7".parse::<Condition>().is_err());
+		assert!("hungry".parse::<Condition>().is_err());
+		assert_eq!(Condition::Exhaustion(2).to_string(), "exhaustion 2");
+	}
+
+	#[test]
+	fn conditions_force_disadvantage() {
+		let mut conditions = Conditions::default();
+		conditions.add(Condition::Prone);
+		assert_eq!(conditions.check_disadvantage(), None);
+		assert_eq!(conditions.attack_disadvantage(), Some(Condition::Prone));
+
+		conditions.add(Condition::Exhaustion(1));
+		assert_eq!(
+			conditions.check_disadvantage(),
+			Some(Condition::Exhaustion(1))
+		);
+		assert_eq!(conditions.saving_throw_disadvantage("Dexterity"), None);
+
+		conditions.add(Condition::Exhaustion(3));
+		assert_eq!(conditions.conditions.len(), 2);
+		assert_eq!(
+			conditions.saving_throw_disadvantage("Wisdom"),
+			Some(Condition::Exhaustion(3))
+		);
+
+		assert!(conditions.remove(Condition::Prone));
+		assert!(!conditions.remove(Condition::Poisoned));
+	}
+}
@@ -0,0 +1,632 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use rand::Rng;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Also the limit for the whole expression, so that the rolled dice fit in a message
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+/// Together with the other limits keeps totals far from overflowing
+const MAX_CONSTANT: i32 = 1_000_000;
+const MAX_TERMS: usize = 20;
+
+/// Which dice to keep after rolling, e.g. `4d6kh3` keeps the three highest.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Keep {
+	Highest(u32),
+	Lowest(u32),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Dice {
+	pub count: u32,
+	pub sides: u32,
+	pub keep: Option<Keep>,
+	pub negative: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+	Dice(Dice),
+	Constant(i32),
+}
+
+/// Dice notation such as `2d6+1d4+3`, `4d6kh3`, `d%` or `1d20-2`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiceExpression {
+	pub terms: Vec<Term>,
+}
+
+fn too_many_dice() -> anyhow::Error {
+	anyhow!("Can only roll 1 to {} dice at once.", MAX_DICE)
+}
+
+impl FromStr for Term {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		lazy_static! {
+			static ref DICE: Regex = Regex::new(
+				r"^(?P<count>\d*)d(?P<sides>\d+|%)(?:k(?P<keep>[hl]?)(?P<keep_count>\d+))?$"
+			)
+			.unwrap();
+		}
+
+		if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+			return match s.parse::<i32>() {
+				Ok(constant) if constant <= MAX_CONSTANT => Ok(Term::Constant(constant)),
+				_ => Err(anyhow!("Numbers can be at most {}.", MAX_CONSTANT)),
+			};
+		}
+
+		let captures = DICE
+			.captures(s)
+			.ok_or_else(|| anyhow!("Cannot parse \"{}\" as dice.", s))?;
+
+		// Numbers too large for u32 are out of range as well
+		let count = match &captures["count"] {
+			"" => Some(1),
+			count => count.parse().ok(),
+		};
+		let count = match count {
+			Some(count) if count > 0 && count <= MAX_DICE => count,
+			_ => return Err(too_many_dice()),
+		};
+		let sides = match &captures["sides"] {
+			"%" => Some(100),
+			sides => sides.parse().ok(),
+		};
+		let sides = match sides {
+			Some(sides) if sides > 0 && sides <= MAX_SIDES => sides,
+			_ => return Err(anyhow!("Dice can have 1 to {} sides.", MAX_SIDES)),
+		};
+
+		let keep = match captures.name("keep_count") {
+			Some(keep_count) => {
+				let keep_count = match keep_count.as_str().parse() {
+					Ok(keep_count) if keep_count > 0 && keep_count <= count => keep_count,
+					_ => {
+						let keep_count = keep_count.as_str();
+						return Err(anyhow!("Cannot keep {} out of {} dice.", keep_count, count));
+					}
+				};
+				match &captures["keep"] {
+					"l" => Some(Keep::Lowest(keep_count)),
+					_ => Some(Keep::Highest(keep_count)),
+				}
+			}
+			None => None,
+		};
+
+		Ok(Term::Dice(Dice {
+			count,
+			sides,
+			keep,
+			negative: false,
+		}))
+	}
+}
+
+impl FromStr for DiceExpression {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s: String = s
+			.chars()
+			.filter(|c| !c.is_whitespace())
+			.collect::<String>()
+			.to_lowercase();
+		if s.is_empty() {
+			return Err(anyhow!("Expected dice such as 2d6+3."));
+		}
+
+		let mut terms = Vec::new();
+		let mut negative = false;
+		let mut start = 0;
+		for (i, c) in s.char_indices().chain(std::iter::once((s.len(), '+'))) {
+			if c != '+' && c != '-' {
+				continue;
+			}
+			// A leading sign applies to the first term
+			if i != 0 {
+				let term = match s[start..i].parse()? {
+					Term::Dice(dice) => Term::Dice(Dice { negative, ..dice }),
+					Term::Constant(constant) if negative => Term::Constant(-constant),
+					term => term,
+				};
+				terms.push(term);
+			}
+			negative = c == '-';
+			start = i + 1;
+		}
+		if terms.len() > MAX_TERMS {
+			return Err(anyhow!("Can only add up to {} terms at once.", MAX_TERMS));
+		}
+		let dice: u32 = terms
+			.iter()
+			.map(|term| match term {
+				Term::Dice(dice) => dice.count,
+				Term::Constant(_) => 0,
+			})
+			.sum();
+		if dice > MAX_DICE {
+			return Err(too_many_dice());
+		}
+
+		Ok(DiceExpression { terms })
+	}
+}
+
+impl Serialize for DiceExpression {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&self.to_string())
+	}
+}
+
+impl<'de> Deserialize<'de> for DiceExpression {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(de::Error::custom)
+	}
+}
+
+impl Display for Dice {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}d{}", self.count, self.sides)?;
+		match self.keep {
+			Some(Keep::Highest(n)) => write!(f, "kh{}", n),
+			Some(Keep::Lowest(n)) => write!(f, "kl{}", n),
+			None => Ok(()),
+		}
+	}
+}
+
+impl Display for DiceExpression {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		for (i, term) in self.terms.iter().enumerate() {
+			match term {
+				Term::Dice(dice) if dice.negative => write!(f, "-{}", dice)?,
+				Term::Dice(dice) if i == 0 => write!(f, "{}", dice)?,
+				Term::Dice(dice) => write!(f, "+{}", dice)?,
+				Term::Constant(constant) if i == 0 => write!(f, "{}", constant)?,
+				Term::Constant(constant) => write!(f, "{:+}", constant)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+/// A single rolled die and whether it counts towards the total.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Die {
+	pub value: i32,
+	pub kept: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TermRoll {
+	Dice { dice: Vec<Die>, negative: bool },
+	Constant(i32),
+}
+
+impl TermRoll {
+	fn total(&self) -> i32 {
+		match self {
+			TermRoll::Dice { dice, negative } => {
+				let sum: i32 = dice
+					.iter()
+					.filter(|die| die.kept)
+					.map(|die| die.value)
+					.sum();
+				if *negative {
+					-sum
+				} else {
+					sum
+				}
+			}
+			TermRoll::Constant(constant) => *constant,
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Roll {
+	pub terms: Vec<TermRoll>,
+}
+
+impl Roll {
+	pub fn total(&self) -> i32 {
+		self.terms.iter().map(TermRoll::total).sum()
+	}
+}
+
+/// Strikes text out with combining characters, since messages are sent as plain text.
+pub fn strikethrough(text: &str) -> String {
+	text.chars().flat_map(|c| vec![c, '\u{0336}']).collect()
+}
+
+impl Display for Die {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if self.kept {
+			self.value.fmt(f)
+		} else {
+			f.write_str(&strikethrough(&self.value.to_string()))
+		}
+	}
+}
+
+impl Display for Roll {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		for (i, term) in self.terms.iter().enumerate() {
+			let sign = match term {
+				TermRoll::Dice { negative: true, .. } => "-",
+				TermRoll::Constant(constant) if *constant < 0 => "-",
+				_ if i == 0 => "",
+				_ => "+",
+			};
+			if i == 0 {
+				f.write_str(sign)?;
+			} else {
+				write!(f, " {} ", sign)?;
+			}
+			match term {
+				TermRoll::Dice { dice, .. } => {
+					let dice: Vec<String> = dice.iter().map(Die::to_string).collect();
+					write!(f, "[{}]🎲", dice.join(", "))?;
+				}
+				TermRoll::Constant(constant) => write!(f, "{}", constant.abs())?,
+			}
+		}
+		write!(f, " = {}", self.total())
+	}
+}
+
+pub fn roll_die<R: Rng + ?Sized>(rng: &mut R, sides: u32) -> i32 {
+	rng.gen_range(1..=sides as i32)
+}
+
+impl Dice {
+	pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<Die> {
+		let mut dice: Vec<Die> = (0..self.count)
+			.map(|_| Die {
+				value: roll_die(rng, self.sides),
+				kept: true,
+			})
+			.collect();
+
+		if let Some(keep) = self.keep {
+			let mut order: Vec<usize> = (0..dice.len()).collect();
+			let (keep_count, highest) = match keep {
+				Keep::Highest(n) => (n as usize, true),
+				Keep::Lowest(n) => (n as usize, false),
+			};
+			order.sort_by_key(|&i| dice[i].value);
+			if highest {
+				order.reverse();
+			}
+			for &i in &order[keep_count..] {
+				dice[i].kept = false;
+			}
+		}
+
+		dice
+	}
+}
+
+impl DiceExpression {
+	/// Doubles the number of dice rolled, as for a critical hit
+	pub fn critical(&self) -> DiceExpression {
+		let terms = self
+			.terms
+			.iter()
+			.map(|term| match term {
+				Term::Dice(dice) => Term::Dice(Dice {
+					count: dice.count * 2,
+					..*dice
+				}),
+				term => *term,
+			})
+			.collect();
+		DiceExpression { terms }
+	}
+
+	/// The only d20 kept in a roll of this expression, as in "1d20+5" or "2d20kh1"
+	pub fn single_d20(&self, roll: &Roll) -> Option<i32> {
+		let kept: Vec<i32> = self
+			.terms
+			.iter()
+			.zip(&roll.terms)
+			.filter_map(|(term, term_roll)| match (term, term_roll) {
+				(Term::Dice(Dice { sides: 20, .. }), TermRoll::Dice { dice, .. }) => Some(dice),
+				_ => None,
+			})
+			.flatten()
+			.filter(|die| die.kept)
+			.map(|die| die.value)
+			.collect();
+		match kept.as_slice() {
+			[d20] => Some(*d20),
+			_ => None,
+		}
+	}
+
+	pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Roll {
+		let terms = self
+			.terms
+			.iter()
+			.map(|term| match term {
+				Term::Dice(dice) => TermRoll::Dice {
+					dice: dice.roll(rng),
+					negative: dice.negative,
+				},
+				Term::Constant(constant) => TermRoll::Constant(*constant),
+			})
+			.collect();
+		Roll { terms }
+	}
+}
+
+/// Whether a d20 is rolled twice, keeping the higher or the lower result.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RollMode {
+	Normal,
+	Advantage,
+	Disadvantage,
+}
+
+impl FromStr for RollMode {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"adv" | "advantage" => Ok(RollMode::Advantage),
+			"dis" | "disadv" | "disadvantage" => Ok(RollMode::Disadvantage),
+			_ => Err(anyhow!("Expected \"adv\" or \"dis\".")),
+		}
+	}
+}
+
+impl RollMode {
+	/// Advantage and disadvantage cancel out, no matter how many sources of each there are
+	pub fn new(advantage: bool, disadvantage: bool) -> RollMode {
+		match (advantage, disadvantage) {
+			(true, false) => RollMode::Advantage,
+			(false, true) => RollMode::Disadvantage,
+			_ => RollMode::Normal,
+		}
+	}
+
+	/// Rolls the d20, returning the kept die and the discarded one if there was a second roll.
+	pub fn roll_d20<R: Rng + ?Sized>(self, rng: &mut R) -> (i32, Option<i32>) {
+		let first = roll_die(rng, 20);
+		match self {
+			RollMode::Normal => (first, None),
+			RollMode::Advantage => {
+				let second = roll_die(rng, 20);
+				(first.max(second), Some(first.min(second)))
+			}
+			RollMode::Disadvantage => {
+				let second = roll_die(rng, 20);
+				(first.min(second), Some(first.max(second)))
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Dice, DiceExpression, Die, Keep, Roll, RollMode, Term, TermRoll};
+
+	#[test]
+	fn parse_dice_expression() {
+		let expression: DiceExpression = "2d6 + 1d4 + 3".parse().unwrap();
+		assert_eq!(
+			expression.terms,
+			vec![
+				Term::Dice(Dice {
+					count: 2,
+					sides: 6,
+					keep: None,
+					negative: false
+				}),
+				Term::Dice(Dice {
+					count: 1,
+					sides: 4,
+					keep: None,
+					negative: false
+				}),
+				Term::Constant(3),
+			]
+		);
+		assert_eq!(expression.to_string(), "2d6+1d4+3");
+	}
+
+	#[test]
+	fn parse_dice_shorthands() {
+		let expression: DiceExpression = "4d6kh3".parse().unwrap();
+		assert_eq!(
+			expression.terms,
+			vec![Term::Dice(Dice {
+				count: 4,
+				sides: 6,
+				keep: Some(Keep::Highest(3)),
+				negative: false
+			})]
+		);
+		assert_eq!("d%".parse::<DiceExpression>().unwrap().to_string(), "1d100");
+		assert_eq!(
+			"1d20-2".parse::<DiceExpression>().unwrap().to_string(),
+			"1d20-2"
+		);
+		assert_eq!(
+			"-1d4".parse::<DiceExpression>().unwrap().to_string(),
+			"-1d4"
+		);
+	}
+
+	#[test]
+	fn double_dice_on_critical() {
+		let expression: DiceExpression = "1d8+2d6+3".parse().unwrap();
+		assert_eq!(expression.critical().to_string(), "2d8+4d6+3");
+	}
+
+	#[test]
+	fn serialize_dice_expression_as_string() {
+		let expression: DiceExpression = "4d6kh3+1".parse().unwrap();
+		let json = serde_json::to_string(&expression).unwrap();
+		assert_eq!(json, "\"4d6kh3+1\"");
+		assert_eq!(
+			serde_json::from_str::<DiceExpression>(&json).unwrap(),
+			expression
+		);
+	}
+
+	#[test]
+	fn parse_invalid_dice() {
+		assert!("".parse::<DiceExpression>().is_err());
+		assert!("2d".parse::<DiceExpression>().is_err());
+		assert!("2d6+".parse::<DiceExpression>().is_err());
+		assert!("0d6".parse::<DiceExpression>().is_err());
+		assert!("2d6kh3".parse::<DiceExpression>().is_err());
+	}
+
+	#[test]
+	fn limit_totals() {
+		assert!("2147483647+1".parse::<DiceExpression>().is_err());
+		assert!("99999999999".parse::<DiceExpression>().is_err());
+		assert!("-1000001".parse::<DiceExpression>().is_err());
+		assert!("1000000-1000000".parse::<DiceExpression>().is_ok());
+		assert!(["1"; 21].join("+").parse::<DiceExpression>().is_err());
+
+		let expression: DiceExpression =
+			["5d1000", "1000000"].repeat(10).join("+").parse().unwrap();
+		let roll = expression.roll(&mut rand::thread_rng());
+		assert!(roll.total() > 10_000_000);
+		assert!(roll.to_string().len() < 4096);
+	}
+
+	#[test]
+	fn limit_dice_in_whole_expression() {
+		assert!("100d1000".parse::<DiceExpression>().is_ok());
+		let error = "60d6+50d8".parse::<DiceExpression>().unwrap_err();
+		assert_eq!(error.to_string(), "Can only roll 1 to 100 dice at once.");
+		let error = "99999999999d6".parse::<DiceExpression>().unwrap_err();
+		assert_eq!(error.to_string(), "Can only roll 1 to 100 dice at once.");
+		let error = "d99999999999".parse::<DiceExpression>().unwrap_err();
+		assert_eq!(error.to_string(), "Dice can have 1 to 1000 sides.");
+		let error = "4d6k99999999999".parse::<DiceExpression>().unwrap_err();
+		assert_eq!(error.to_string(), "Cannot keep 99999999999 out of 4 dice.");
+
+		let roll = "100d1000"
+			.parse::<DiceExpression>()
+			.unwrap()
+			.roll(&mut rand::thread_rng());
+		assert!(roll.to_string().len() < 4096);
+	}
+
+	#[test]
+	fn roll_keeps_highest() {
+		let expression: DiceExpression = "10d6kh3".parse().unwrap();
+		let roll = expression.roll(&mut rand::thread_rng());
+		match &roll.terms[..] {
+			[TermRoll::Dice { dice, .. }] => {
+				let kept: Vec<i32> = dice.iter().filter(|d| d.kept).map(|d| d.value).collect();
+				let dropped = dice.iter().filter(|d| !d.kept).map(|d| d.value).max();
+				assert_eq!(kept.len(), 3);
+				assert!(kept.iter().all(|&value| value >= dropped.unwrap()));
+			}
+			_ => panic!("Expected a single dice term"),
+		}
+	}
+
+	#[test]
+	fn print_roll() {
+		let roll = Roll {
+			terms: vec![
+				TermRoll::Dice {
+					dice: vec![
+						Die {
+							value: 3,
+							kept: true,
+						},
+						Die {
+							value: 5,
+							kept: true,
+						},
+					],
+					negative: false,
+				},
+				TermRoll::Dice {
+					dice: vec![Die {
+						value: 2,
+						kept: true,
+					}],
+					negative: true,
+				},
+				TermRoll::Constant(3),
+			],
+		};
+		assert_eq!(roll.to_string(), "[3, 5]🎲 - [2]🎲 + 3 = 9");
+	}
+
+	#[test]
+	fn print_dropped_die() {
+		let roll = Roll {
+			terms: vec![TermRoll::Dice {
+				dice: vec![
+					Die {
+						value: 1,
+						kept: false,
+					},
+					Die {
+						value: 12,
+						kept: true,
+					},
+				],
+				negative: false,
+			}],
+		};
+		assert_eq!(roll.to_string(), "[1\u{336}, 12]🎲 = 12");
+	}
+
+	#[test]
+	fn roll_with_advantage() {
+		for _ in 0..100 {
+			let (kept, discarded) = RollMode::Advantage.roll_d20(&mut rand::thread_rng());
+			assert!(kept >= discarded.unwrap());
+			let (kept, discarded) = RollMode::Disadvantage.roll_d20(&mut rand::thread_rng());
+			assert!(kept <= discarded.unwrap());
+		}
+	}
+
+	#[test]
+	fn find_single_d20() {
+		let expression: DiceExpression = "2d20kh1+5".parse().unwrap();
+		let roll = Roll {
+			terms: vec![
+				TermRoll::Dice {
+					dice: vec![
+						Die {
+							value: 20,
+							kept: true,
+						},
+						Die {
+							value: 1,
+							kept: false,
+						},
+					],
+					negative: false,
+				},
+				TermRoll::Constant(5),
+			],
+		};
+		assert_eq!(expression.single_d20(&roll), Some(20));
+
+		let expression: DiceExpression = "2d6kh1+5".parse().unwrap();
+		assert_eq!(expression.single_d20(&roll), None);
+	}
+}
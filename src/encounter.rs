@@ -0,0 +1,205 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use telegram_bot::UserId;
+
+use crate::redis_json::redis_json;
+
+/// Someone in the initiative order: a player's character or a monster added by the DM
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Combatant {
+	pub name: String,
+	/// The player controlling the character, or `None` for monsters
+	pub player: Option<UserId>,
+	pub bonus: i32,
+	pub d20: i32,
+}
+
+impl Combatant {
+	pub fn initiative(&self) -> i32 {
+		self.d20 + self.bonus
+	}
+}
+
+/// Initiative order of a chat's current fight
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encounter {
+	/// The user who started the encounter and manages monsters and turns
+	pub dm: UserId,
+	pub combatants: Vec<Combatant>,
+	/// Name of the combatant whose turn it is, `None` until the first turn
+	pub current: Option<String>,
+	pub round: u32,
+}
+
+impl Encounter {
+	pub fn new(dm: UserId) -> Encounter {
+		Encounter {
+			dm,
+			combatants: Vec::new(),
+			current: None,
+			round: 0,
+		}
+	}
+
+	/// The character of the player, if they have rolled initiative
+	pub fn find_player(&self, player: UserId) -> Option<&Combatant> {
+		self.combatants
+			.iter()
+			.find(|combatant| combatant.player == Some(player))
+	}
+
+	/// Adds a combatant in initiative order. Combatants sharing a name are numbered,
+	/// since turns are tracked by name.
+	pub fn add(&mut self, mut combatant: Combatant) -> &Combatant {
+		let base = combatant.name.clone();
+		let mut number = 1;
+		while self
+			.combatants
+			.iter()
+			.any(|other| other.name == combatant.name)
+		{
+			number += 1;
+			combatant.name = format!("{} {}", base, number);
+		}
+
+		// Ties go to the higher bonus, then to whoever rolled first
+		let index = self
+			.combatants
+			.iter()
+			.position(|other| {
+				(other.initiative(), other.bonus) < (combatant.initiative(), combatant.bonus)
+			})
+			.unwrap_or(self.combatants.len());
+		self.combatants.insert(index, combatant);
+		&self.combatants[index]
+	}
+
+	/// Advances to the next combatant, starting a new round after the last one
+	pub fn next_turn(&mut self) -> Option<&Combatant> {
+		let current = self.current.as_ref().and_then(|current| {
+			self.combatants
+				.iter()
+				.position(|combatant| &combatant.name == current)
+		});
+		let next = match current {
+			Some(index) if index + 1 < self.combatants.len() => index + 1,
+			_ => {
+				self.round += 1;
+				0
+			}
+		};
+
+		let combatant = self.combatants.get(next)?;
+		self.current = Some(combatant.name.clone());
+		Some(combatant)
+	}
+}
+
+impl Display for Encounter {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if self.combatants.is_empty() {
+			return write!(f, "Nobody has rolled initiative yet.");
+		}
+		if self.round > 0 {
+			writeln!(f, "Round {}:", self.round)?;
+		}
+		let lines: Vec<String> = self
+			.combatants
+			.iter()
+			.map(|combatant| {
+				let marker = if Some(&combatant.name) == self.current.as_ref() {
+					"▶️ "
+				} else {
+					""
+				};
+				format!("{}{} {}", marker, combatant.initiative(), combatant.name)
+			})
+			.collect();
+		f.write_str(&lines.join("\n"))
+	}
+}
+
+redis_json!(Encounter);
+
+#[cfg(test)]
+mod tests {
+	use super::{Combatant, Encounter};
+	use telegram_bot::UserId;
+
+	fn monster(name: &str, bonus: i32, d20: i32) -> Combatant {
+		Combatant {
+			name: name.to_string(),
+			player: None,
+			bonus,
+			d20,
+		}
+	}
+
+	#[test]
+	fn keep_initiative_order() {
+		let mut encounter = Encounter::new(UserId::new(1));
+		encounter.add(monster("Goblin", 2, 10));
+		encounter.add(monster("Ogre", -1, 20));
+		encounter.add(monster("Wolf", 3, 9));
+		encounter.add(monster("Goblin", 2, 3));
+
+		let names: Vec<&str> = encounter
+			.combatants
+			.iter()
+			.map(|combatant| combatant.name.as_str())
+			.collect();
+		assert_eq!(names, vec!["Ogre", "Wolf", "Goblin", "Goblin 2"]);
+	}
+
+	#[test]
+	fn find_players_who_rolled() {
+		let mut encounter = Encounter::new(UserId::new(1));
+		encounter.add(monster("Goblin", 2, 10));
+		assert_eq!(encounter.find_player(UserId::new(2)), None);
+
+		encounter.add(Combatant {
+			name: "Legolas".to_string(),
+			player: Some(UserId::new(2)),
+			bonus: 4,
+			d20: 3,
+		});
+		let legolas = encounter.find_player(UserId::new(2)).unwrap();
+		assert_eq!(legolas.name, "Legolas");
+		assert_eq!(legolas.initiative(), 7);
+	}
+
+	#[test]
+	fn advance_turns_and_rounds() {
+		let mut encounter = Encounter::new(UserId::new(1));
+		encounter.add(monster("Goblin", 2, 10));
+		encounter.add(monster("Wolf", 3, 5));
+
+		assert_eq!(encounter.next_turn().unwrap().name, "Goblin");
+		assert_eq!(encounter.round, 1);
+		assert_eq!(encounter.next_turn().unwrap().name, "Wolf");
+		assert_eq!(encounter.next_turn().unwrap().name, "Goblin");
+		assert_eq!(encounter.round, 2);
+		assert_eq!(encounter.to_string(), "Round 2:\n▶️ 12 Goblin\n8 Wolf");
+	}
+
+	#[test]
+	fn give_every_player_a_turn() {
+		let mut encounter = Encounter::new(UserId::new(1));
+		for (player, d20) in [(2, 15), (3, 5)].iter() {
+			encounter.add(Combatant {
+				name: "Demo".to_string(),
+				player: Some(UserId::new(*player)),
+				bonus: 0,
+				d20: *d20,
+			});
+		}
+		encounter.add(monster("Goblin", 0, 10));
+
+		let turns: Vec<String> = (0..4)
+			.map(|_| encounter.next_turn().unwrap().name.clone())
+			.collect();
+		assert_eq!(turns, vec!["Demo", "Goblin", "Demo 2", "Demo"]);
+		assert_eq!(encounter.round, 2);
+	}
+}
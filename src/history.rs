@@ -0,0 +1,166 @@
+use redis::streams::StreamId;
+
+/// How many rolls each chat keeps, roughly
+pub const MAX_ROLLS: usize = 10_000;
+
+/// A roll the bot made, as kept in the chat's roll history
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RollRecord {
+	pub user_id: i64,
+	/// First name of the user, empty if the bot rolled for them as part of a group check
+	pub user_name: String,
+	/// Telegram username without the "@"
+	pub username: Option<String>,
+	pub character: Option<String>,
+	/// What was rolled, such as "Stealth check" or "2d6+3"
+	pub roll: String,
+	/// The dice and the modifier as shown in the reply, such as "5💪+[14, 3̶]🎲 = 19"
+	pub dice: String,
+	/// The natural d20, if the roll was a single d20
+	pub d20: Option<i32>,
+	/// The other d20 of a roll with advantage or disadvantage
+	pub discarded_d20: Option<i32>,
+	pub modifier: i32,
+	pub total: i32,
+	/// Unix time in seconds
+	pub timestamp: u64,
+	pub hidden: bool,
+}
+
+impl RollRecord {
+	/// Fields of the stream entry
+	pub fn fields(&self) -> Vec<(&'static str, String)> {
+		let mut fields = vec![
+			("user_id", self.user_id.to_string()),
+			("user_name", self.user_name.clone()),
+			("roll", self.roll.clone()),
+			("dice", self.dice.clone()),
+			("modifier", self.modifier.to_string()),
+			("total", self.total.to_string()),
+			("timestamp", self.timestamp.to_string()),
+			("hidden", (self.hidden as u8).to_string()),
+		];
+		if let Some(username) = &self.username {
+			fields.push(("username", username.clone()));
+		}
+		if let Some(character) = &self.character {
+			fields.push(("character", character.clone()));
+		}
+		if let Some(d20) = self.d20 {
+			fields.push(("d20", d20.to_string()));
+		}
+		if let Some(discarded_d20) = self.discarded_d20 {
+			fields.push(("discarded_d20", discarded_d20.to_string()));
+		}
+		fields
+	}
+
+	pub fn from_stream_id(entry: &StreamId) -> Option<RollRecord> {
+		Some(RollRecord {
+			user_id: entry.get("user_id")?,
+			user_name: entry.get("user_name").unwrap_or_default(),
+			username: entry.get("username"),
+			character: entry.get("character"),
+			roll: entry.get("roll")?,
+			dice: entry.get("dice")?,
+			d20: entry.get("d20"),
+			discarded_d20: entry.get("discarded_d20"),
+			modifier: entry.get("modifier")?,
+			total: entry.get("total")?,
+			timestamp: entry.get("timestamp")?,
+			hidden: entry.get::<u8>("hidden").unwrap_or(0) != 0,
+		})
+	}
+
+	/// Who rolled, by first name, falling back to the character for group checks
+	pub fn name(&self) -> &str {
+		match &self.character {
+			Some(character) if self.user_name.is_empty() => character,
+			_ => &self.user_name,
+		}
+	}
+
+	/// Whether "@anna" or "anna" refers to the user who rolled, by username or first name
+	pub fn is_by(&self, user: &str) -> bool {
+		let user = user.trim_start_matches('@');
+		self.username
+			.iter()
+			.chain(std::iter::once(&self.user_name))
+			.any(|name| !name.is_empty() && name.eq_ignore_ascii_case(user))
+	}
+
+	fn roller(&self) -> String {
+		match &self.character {
+			Some(character) if !self.user_name.is_empty() => {
+				format!("{} ({})", self.user_name, character)
+			}
+			Some(character) => character.clone(),
+			None => self.user_name.clone(),
+		}
+	}
+
+	/// Formats the roll as a line of the history, such as "5m ago, Anna (Gimli): Stealth check: ..."
+	pub fn format(&self, now: u64) -> String {
+		let age = format_age(now.saturating_sub(self.timestamp));
+		if self.hidden {
+			format!("{}, {}: 🎲 rolled secretly", age, self.roller())
+		} else {
+			format!("{}, {}: {}: {}", age, self.roller(), self.roll, self.dice)
+		}
+	}
+}
+
+fn format_age(seconds: u64) -> String {
+	match seconds {
+		0..=59 => "just now".to_string(),
+		60..=3599 => format!("{}m ago", seconds / 60),
+		3600..=86399 => format!("{}h ago", seconds / 3600),
+		_ => format!("{}d ago", seconds / 86400),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::RollRecord;
+
+	fn record() -> RollRecord {
+		RollRecord {
+			user_id: 1,
+			user_name: "Anna".to_string(),
+			username: Some("anna_the_bard".to_string()),
+			character: Some("Gimli".to_string()),
+			roll: "Stealth check".to_string(),
+			dice: "5💪+14🎲 = 19".to_string(),
+			d20: Some(14),
+			discarded_d20: None,
+			modifier: 5,
+			total: 19,
+			timestamp: 1000,
+			hidden: false,
+		}
+	}
+
+	#[test]
+	fn print_history_line() {
+		assert_eq!(
+			record().format(1300),
+			"5m ago, Anna (Gimli): Stealth check: 5💪+14🎲 = 19"
+		);
+
+		let hidden = RollRecord {
+			hidden: true,
+			..record()
+		};
+		assert_eq!(
+			hidden.format(1000 + 7200),
+			"2h ago, Anna (Gimli): 🎲 rolled secretly"
+		);
+	}
+
+	#[test]
+	fn find_rolls_by_user() {
+		assert!(record().is_by("@anna_the_bard"));
+		assert!(record().is_by("anna"));
+		assert!(!record().is_by("@gimli"));
+	}
+}
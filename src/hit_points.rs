@@ -0,0 +1,177 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::redis_json::redis_json;
+
+/// Current, maximum and temporary hit points of a character
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HitPoints {
+	pub current: i32,
+	pub max: i32,
+	pub temp: i32,
+}
+
+impl HitPoints {
+	/// A fully rested character
+	pub fn new(max: i32) -> HitPoints {
+		HitPoints {
+			current: max,
+			max,
+			temp: 0,
+		}
+	}
+
+	/// Takes a new maximum from a refreshed character sheet
+	pub fn with_max(self, max: i32) -> HitPoints {
+		HitPoints {
+			current: self.current.min(max),
+			max,
+			..self
+		}
+	}
+
+	/// Temporary hit points absorb the damage first, and hit points never go below 0
+	pub fn damage(&mut self, amount: i32) {
+		let absorbed = amount.min(self.temp);
+		self.temp -= absorbed;
+		self.current = (self.current - (amount - absorbed)).max(0);
+	}
+
+	/// Healing never raises hit points above the maximum
+	pub fn heal(&mut self, amount: i32) {
+		self.current = (self.current + amount).min(self.max);
+	}
+
+	/// Temporary hit points don't stack, so the character keeps whichever is higher
+	pub fn add_temp(&mut self, amount: i32) {
+		self.temp = self.temp.max(amount);
+	}
+
+	pub fn is_down(&self) -> bool {
+		self.current == 0
+	}
+}
+
+impl Display for HitPoints {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}/{} HP", self.current, self.max)?;
+		if self.temp > 0 {
+			write!(f, " (+{} temp)", self.temp)?;
+		}
+		Ok(())
+	}
+}
+
+/// Death saving throws of a character at 0 hit points
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeathSaves {
+	pub successes: u32,
+	pub failures: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeathSave {
+	Success,
+	Failure,
+	Stable,
+	Dead,
+	/// A natural 20 brings the character back with 1 hit point
+	Revived,
+}
+
+impl DeathSaves {
+	pub fn is_stable(&self) -> bool {
+		self.successes >= 3
+	}
+
+	pub fn is_dead(&self) -> bool {
+		self.failures >= 3
+	}
+
+	/// Records a death saving throw. A natural 1 counts as two failures.
+	pub fn record(&mut self, d20: i32) -> DeathSave {
+		match d20 {
+			20 => {
+				*self = DeathSaves::default();
+				return DeathSave::Revived;
+			}
+			1 => self.failures += 2,
+			d20 if d20 < 10 => self.failures += 1,
+			_ => self.successes += 1,
+		}
+
+		if self.is_dead() {
+			DeathSave::Dead
+		} else if self.is_stable() {
+			DeathSave::Stable
+		} else if d20 < 10 {
+			DeathSave::Failure
+		} else {
+			DeathSave::Success
+		}
+	}
+}
+
+redis_json!(HitPoints);
+redis_json!(DeathSaves);
+
+#[cfg(test)]
+mod tests {
+	use super::{DeathSave, DeathSaves, HitPoints};
+
+	#[test]
+	fn temp_hit_points_absorb_damage_first() {
+		let mut hit_points = HitPoints::new(20);
+		hit_points.add_temp(5);
+		hit_points.damage(3);
+		assert_eq!(hit_points.to_string(), "20/20 HP (+2 temp)");
+		hit_points.damage(7);
+		assert_eq!(hit_points.to_string(), "15/20 HP");
+		hit_points.damage(30);
+		assert_eq!(hit_points.current, 0);
+		assert!(hit_points.is_down());
+	}
+
+	#[test]
+	fn heal_up_to_max() {
+		let mut hit_points = HitPoints::new(20);
+		hit_points.damage(8);
+		hit_points.heal(50);
+		assert_eq!(hit_points.current, 20);
+	}
+
+	#[test]
+	fn temp_hit_points_do_not_stack() {
+		let mut hit_points = HitPoints::new(20);
+		hit_points.add_temp(8);
+		hit_points.add_temp(5);
+		assert_eq!(hit_points.temp, 8);
+	}
+
+	#[test]
+	fn count_death_saves() {
+		let mut death_saves = DeathSaves::default();
+		assert_eq!(death_saves.record(12), DeathSave::Success);
+		assert_eq!(death_saves.record(4), DeathSave::Failure);
+		assert_eq!(death_saves.record(10), DeathSave::Success);
+		assert_eq!(death_saves.record(19), DeathSave::Stable);
+		assert!(death_saves.is_stable());
+	}
+
+	#[test]
+	fn natural_one_counts_twice() {
+		let mut death_saves = DeathSaves::default();
+		assert_eq!(death_saves.record(1), DeathSave::Failure);
+		assert_eq!(death_saves.failures, 2);
+		assert_eq!(death_saves.record(9), DeathSave::Dead);
+	}
+
+	#[test]
+	fn natural_twenty_revives() {
+		let mut death_saves = DeathSaves::default();
+		death_saves.record(3);
+		assert_eq!(death_saves.record(20), DeathSave::Revived);
+		assert_eq!(death_saves, DeathSaves::default());
+	}
+}
@@ -0,0 +1,95 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::anyhow;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::redis_json::redis_json;
+
+/// What happens when an attack roll comes up a natural 1 and fumbles are on
+const FUMBLES: [&str; 10] = [
+	"You overextend and fall prone.",
+	"Your weapon slips from your grip and lands 10 feet away.",
+	"You hit yourself for half of the damage.",
+	"You stumble into an ally, who must succeed on a DC 10 Dexterity saving throw or fall prone.",
+	"You leave yourself open: the next attack against you has advantage.",
+	"Something gets in your eyes. You are blinded until the end of your next turn.",
+	"You pull a muscle and have disadvantage on attacks until the end of your next turn.",
+	"Your weapon gets stuck. It takes your next action to free it.",
+	"You lose your footing and your speed is halved until the end of your next turn.",
+	"A wild swing hits the nearest ally for half of the damage.",
+];
+
+/// A house rule that a chat can turn on or off
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HouseRule {
+	/// Natural 20s succeed and natural 1s fail on ability checks, not just on attacks
+	CriticalChecks,
+	/// Natural 1s on attack rolls roll on the fumble table
+	Fumbles,
+}
+
+impl FromStr for HouseRule {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"crits" | "criticals" => Ok(HouseRule::CriticalChecks),
+			"fumbles" => Ok(HouseRule::Fumbles),
+			_ => Err(anyhow!("Expected \"crits\" or \"fumbles\".")),
+		}
+	}
+}
+
+/// House rules of a chat. All of them are off by default.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HouseRules {
+	pub critical_checks: bool,
+	pub fumbles: bool,
+}
+
+impl HouseRules {
+	pub fn set(&mut self, rule: HouseRule, on: bool) {
+		match rule {
+			HouseRule::CriticalChecks => self.critical_checks = on,
+			HouseRule::Fumbles => self.fumbles = on,
+		}
+	}
+
+	/// Rolls on the fumble table if fumbles are on
+	pub fn fumble<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&'static str> {
+		if self.fumbles {
+			FUMBLES.choose(rng).copied()
+		} else {
+			None
+		}
+	}
+}
+
+impl Display for HouseRules {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let on_off = |on| if on { "on" } else { "off" };
+		write!(
+			f,
+			"Critical success and failure on checks (crits): {}\nFumble table on natural 1 attacks (fumbles): {}",
+			on_off(self.critical_checks),
+			on_off(self.fumbles)
+		)
+	}
+}
+
+redis_json!(HouseRules);
+
+#[cfg(test)]
+mod tests {
+	use super::{HouseRule, HouseRules};
+
+	#[test]
+	fn fumble_only_when_on() {
+		let mut house_rules = HouseRules::default();
+		assert_eq!(house_rules.fumble(&mut rand::thread_rng()), None);
+
+		house_rules.set("fumbles".parse::<HouseRule>().unwrap(), true);
+		assert!(house_rules.fumble(&mut rand::thread_rng()).is_some());
+	}
+}
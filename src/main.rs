@@ -1,45 +1,269 @@
+mod character_service;
 mod character_sheet;
+mod command;
+mod conditions;
+mod dice;
+mod encounter;
+mod history;
+mod hit_points;
+mod house_rules;
+mod redis_json;
+mod stats;
 mod telegram;
+mod webhook;
 
-use std::{convert::TryFrom, env, fmt::Display};
+use std::{
+	collections::HashMap,
+	convert::TryFrom,
+	env,
+	fmt::Display,
+	sync::{Arc, Mutex},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::anyhow;
-use character_sheet::Headless;
+use character_service::CharacterService;
+use character_sheet::{Attack, CharacterSheet, CharacterSource, Headless, ABILITIES};
+use command::Command;
+use conditions::{Condition, Conditions};
+use dice::{DiceExpression, Roll, RollMode, TermRoll};
+use encounter::{Combatant, Encounter};
+use history::RollRecord;
+use hit_points::{DeathSave, DeathSaves, HitPoints};
+use house_rules::{HouseRule, HouseRules};
 use lazy_static::lazy_static;
-use rand::Rng;
-use redis::{AsyncCommands, Client as Redis, FromRedisValue, ToRedisArgs};
+use redis::{
+	streams::{StreamMaxlen, StreamRangeReply},
+	AsyncCommands, Client as Redis, FromRedisValue, ToRedisArgs,
+};
 use regex::Regex;
-use rocket::{get, launch, post, routes, tokio, Rocket, State};
+use rocket::{get, http::Status, launch, post, routes, tokio, Rocket, State};
 use rocket_contrib::json::Json;
+use stats::PlayerStats;
 use strsim::damerau_levenshtein as edit_distance;
-use telegram_bot::{ChatId, Message, MessageId, MessageKind, Update, UpdateKind, User, UserId};
-use url::Url;
+use telegram::MenuCommand;
+use telegram_bot::{
+	CallbackQuery, CallbackQueryId, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message,
+	MessageId, MessageKind, MessageOrChannelPost, Update, UpdateKind, User, UserId,
+};
+use webhook::{FromTelegram, Webhook};
 
+#[derive(Clone)]
 struct RequestSource {
 	chat_id: ChatId,
 	message_id: MessageId,
 	user_id: UserId,
+	/// First name of the user, to tell the DM who rolled
+	user_name: String,
+	username: Option<String>,
 }
 
 impl RequestSource {
-	async fn respond(&self, token: &str, message: &str) {
-		telegram::send_message(token, self.chat_id, message, self.message_id).await;
+	/// Private chats with the bot share their ID with the user
+	fn is_private_chat(&self) -> bool {
+		i64::from(self.chat_id) == i64::from(self.user_id)
 	}
+
+	async fn respond(&self, token: &str, reply: &Reply) -> bool {
+		telegram::send_message(
+			token,
+			self.chat_id,
+			&reply.text,
+			Some(self.message_id),
+			reply.keyboard.as_ref(),
+		)
+		.await
+	}
+
+	/// Sends the reply to the user's private chat with the bot, returning whether it was sent
+	async fn respond_privately(&self, token: &str, reply: &Reply) -> bool {
+		let chat_id = ChatId::new(self.user_id.into());
+		telegram::send_message(token, chat_id, &reply.text, None, reply.keyboard.as_ref()).await
+	}
+}
+
+struct Reply {
+	text: String,
+	keyboard: Option<InlineKeyboardMarkup>,
+}
+
+impl From<String> for Reply {
+	fn from(text: String) -> Self {
+		Reply {
+			text,
+			keyboard: None,
+		}
+	}
+}
+
+/// A skill check, ability check or saving throw
+struct CheckRequest {
+	source: RequestSource,
+	kind: CheckKind,
+	/// The skill or ability to roll, matched loosely against the character sheet
+	query: String,
+	roll_mode: RollMode,
+	/// Difficulty class to adjudicate the check against
+	dc: Option<i32>,
+	/// Only the roller and the DM get to see the result
+	hidden: bool,
+}
+
+struct AttackRequest {
+	source: RequestSource,
+	attack: String,
+	roll_mode: RollMode,
+}
+
+struct RollRequest {
+	source: RequestSource,
+	expression: DiceExpression,
+	hidden: bool,
 }
 
-struct SkillCheckRequest {
+struct GroupCheckRequest {
 	source: RequestSource,
 	skill: String,
+	roll_mode: RollMode,
+	dc: Option<i32>,
+}
+
+enum HouseRulesAction {
+	List,
+	Set(HouseRule, bool),
+}
+
+struct HouseRulesRequest {
+	source: RequestSource,
+	action: HouseRulesAction,
+}
+
+struct HistoryRequest {
+	source: RequestSource,
+	count: usize,
+	/// Only show rolls by this user, given as "@username" or a first name
+	user: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum StatsPeriod {
+	/// Rolls from the last few hours
+	Session,
+	/// Every roll in the chat's history
+	Campaign,
+}
+
+struct StatsRequest {
+	source: RequestSource,
+	period: StatsPeriod,
+}
+
+struct HelpRequest {
+	source: RequestSource,
+	/// The user has just started a chat with the bot
+	start: bool,
+}
+
+struct RegisterDmRequest {
+	source: RequestSource,
+	/// "/dm off" gives the role up, so that someone else can take it
+	resign: bool,
+}
+
+struct RefreshRequest {
+	source: RequestSource,
+}
+
+enum InitiativeAction {
+	Start,
+	Join(RollMode),
+	AddMonster { name: String, bonus: i32 },
+	Next,
+	List,
+	End,
+}
+
+struct InitiativeRequest {
+	source: RequestSource,
+	action: InitiativeAction,
+}
+
+#[derive(Copy, Clone)]
+enum HitPointsAction {
+	Show,
+	Damage(i32),
+	Heal(i32),
+	AddTemp(i32),
+}
+
+struct HitPointsRequest {
+	source: RequestSource,
+	action: HitPointsAction,
+}
+
+enum ConditionAction {
+	List,
+	Add(Condition),
+	Remove(Condition),
+}
+
+struct ConditionRequest {
+	source: RequestSource,
+	/// The player whose message the command replies to, if it's not about the sender's own character
+	target: Option<UserId>,
+	action: ConditionAction,
+}
+
+struct DeathSaveRequest {
+	source: RequestSource,
 }
 
 struct SetCharacterRequest {
 	source: RequestSource,
 	character_id: CharacterId,
+	/// Defaults to the name on the character sheet
+	name: Option<String>,
+}
+
+struct UseDemoCharacterRequest {
+	source: RequestSource,
+	callback_query_id: CallbackQueryId,
+}
+
+struct ListCharactersRequest {
+	source: RequestSource,
+}
+
+struct UseCharacterRequest {
+	source: RequestSource,
+	name: String,
+}
+
+struct ForgetCharacterRequest {
+	source: RequestSource,
+	name: String,
 }
 
 enum BotCommand {
-	SkillCheck(SkillCheckRequest),
+	Check(CheckRequest),
+	Attack(AttackRequest),
+	Roll(RollRequest),
 	SetCharacter(SetCharacterRequest),
+	UseDemoCharacter(UseDemoCharacterRequest),
+	ListCharacters(ListCharactersRequest),
+	UseCharacter(UseCharacterRequest),
+	ForgetCharacter(ForgetCharacterRequest),
+	Refresh(RefreshRequest),
+	Initiative(InitiativeRequest),
+	HitPoints(HitPointsRequest),
+	DeathSave(DeathSaveRequest),
+	Condition(ConditionRequest),
+	RegisterDm(RegisterDmRequest),
+	GroupCheck(GroupCheckRequest),
+	HouseRules(HouseRulesRequest),
+	History(HistoryRequest),
+	Stats(StatsRequest),
+	Help(HelpRequest),
 	Unknown,
 	Error {
 		source: RequestSource,
@@ -47,16 +271,24 @@ enum BotCommand {
 	},
 }
 
-impl From<Update> for BotCommand {
-	fn from(update: Update) -> Self {
+impl BotCommand {
+	/// Commands addressed to a bot other than the one with `bot_username` are `Unknown`
+	fn from_update(update: Update, bot_username: Option<&str>) -> Self {
 		match update {
 			Update {
 				kind:
 					UpdateKind::Message(Message {
 						chat,
 						id: message_id,
-						from: User { id: user_id, .. },
-						kind: MessageKind::Text { data, .. },
+						from:
+							User {
+								id: user_id,
+								first_name: user_name,
+								username,
+								..
+							},
+						kind: MessageKind::Text { data, entities },
+						reply_to_message,
 						..
 					}),
 				..
@@ -65,51 +297,578 @@ impl From<Update> for BotCommand {
 					chat_id: chat.id(),
 					message_id,
 					user_id,
+					user_name,
+					username,
 				};
-				if data.starts_with("/skill") {
-					BotCommand::SkillCheck(SkillCheckRequest {
-						source,
-						// skip the first 7 characters matching "/skill "
-						skill: data[7..data.len()].to_string(),
-					})
-				} else if data.starts_with("/character") {
-					let character_id = match CharacterId::try_from(&data[11..data.len()]) {
-						Ok(character_id) => character_id,
-						Err(err) => {
-							return BotCommand::Error {
-								source,
-								error: err.to_string(),
-							}
-						}
-					};
-					BotCommand::SetCharacter(SetCharacterRequest {
-						source,
-						character_id,
-					})
-				} else {
-					BotCommand::Unknown
+				let command = match Command::parse(&data, &entities, bot_username) {
+					Some(command) => command,
+					None => return BotCommand::Unknown,
+				};
+				let target = match reply_to_message.as_deref() {
+					Some(MessageOrChannelPost::Message(message)) => Some(message.from.id),
+					_ => None,
+				};
+				let error_source = source.clone();
+				match parse_command(source, &command, target) {
+					Ok(command) => command,
+					Err(err) => BotCommand::Error {
+						source: error_source,
+						error: err.to_string(),
+					},
 				}
 			}
+			Update {
+				kind:
+					UpdateKind::CallbackQuery(CallbackQuery {
+						id: callback_query_id,
+						from:
+							User {
+								id: user_id,
+								first_name: user_name,
+								username,
+								..
+							},
+						message:
+							Some(MessageOrChannelPost::Message(Message {
+								chat,
+								id: message_id,
+								..
+							})),
+						data: Some(data),
+						..
+					}),
+				..
+			} if data == DEMO_CHARACTER_CALLBACK => {
+				let source = RequestSource {
+					chat_id: chat.id(),
+					message_id,
+					user_id,
+					user_name,
+					username,
+				};
+				BotCommand::UseDemoCharacter(UseDemoCharacterRequest {
+					source,
+					callback_query_id,
+				})
+			}
 			_ => BotCommand::Unknown,
 		}
 	}
 }
 
+/// Every command the bot understands, for /help and the command menu in Telegram clients
+const COMMANDS: [(&str, &str); 24] = [
+	("character", "Play the character from a D&D Beyond link"),
+	("characters", "List your characters"),
+	("use", "Switch to another of your characters in this chat"),
+	("forget", "Forget one of your characters"),
+	("refresh", "Reload your character sheet from D&D Beyond"),
+	(
+		"skill",
+		"Roll a skill check, like /skill stealth adv dc15 hidden",
+	),
+	("check", "Roll an ability check, like /check str"),
+	("save", "Roll a saving throw, like /save dex dis"),
+	(
+		"attack",
+		"Attack with a weapon or spell, like /attack longsword",
+	),
+	("roll", "Roll dice, like /roll 2d6+3"),
+	("gmroll", "Roll dice that only you and the DM see"),
+	(
+		"groupcheck",
+		"Roll a skill check for the whole party, like /groupcheck stealth dc12",
+	),
+	(
+		"init",
+		"Roll initiative, or /init start, add, next, list and end",
+	),
+	("hp", "Show your hit points"),
+	("damage", "Take damage, like /damage 7"),
+	("heal", "Regain hit points, like /heal 5"),
+	("temphp", "Gain temporary hit points, like /temphp 8"),
+	("deathsave", "Roll a death saving throw"),
+	(
+		"condition",
+		"List conditions, or /condition add poisoned and /condition remove poisoned",
+	),
+	(
+		"dm",
+		"Become the DM of this chat and see hidden rolls, or step down with /dm off",
+	),
+	(
+		"houserule",
+		"List house rules, or turn them on and off like /houserule fumbles on",
+	),
+	(
+		"history",
+		"Show recent rolls, like /history 20 or /history @user",
+	),
+	(
+		"stats",
+		"Show everyone's d20 luck, for /stats session or the whole campaign",
+	),
+	("help", "List everything the bot can do"),
+];
+
+/// Turns a command into a request. Commands the bot doesn't know are ignored.
+fn parse_command(
+	source: RequestSource,
+	command: &Command,
+	reply_target: Option<UserId>,
+) -> Result<BotCommand, anyhow::Error> {
+	let args = command.args;
+	let bot_command = match command.name.as_str() {
+		"skill" | "check" | "save" => {
+			let (kind, usage) = match command.name.as_str() {
+				"skill" => (CheckKind::SkillCheck, "/skill stealth"),
+				"check" => (CheckKind::AbilityCheck, "/check strength"),
+				_ => (CheckKind::SavingThrow, "/save dex"),
+			};
+			let (args, hidden) = split_hidden(args);
+			let (args, dc) = split_dc(&args);
+			let (query, roll_mode) = split_roll_mode(&args);
+			let query = command.required(&query, usage)?;
+			BotCommand::Check(CheckRequest {
+				source,
+				kind,
+				query: query.to_string(),
+				roll_mode,
+				dc,
+				hidden,
+			})
+		}
+		"attack" => {
+			let (attack, roll_mode) = split_roll_mode(args);
+			let attack = command.required(&attack, "/attack longsword")?;
+			BotCommand::Attack(AttackRequest {
+				source,
+				attack: attack.to_string(),
+				roll_mode,
+			})
+		}
+		"roll" | "gmroll" => {
+			// default to a plain d20 when no dice are given
+			let expression = match args {
+				"" => "1d20",
+				expression => expression,
+			};
+			BotCommand::Roll(RollRequest {
+				source,
+				expression: expression.parse()?,
+				hidden: command.name == "gmroll",
+			})
+		}
+		"groupcheck" => {
+			let (args, dc) = split_dc(args);
+			let (skill, roll_mode) = split_roll_mode(&args);
+			let skill = command.required(&skill, "/groupcheck stealth dc12")?;
+			BotCommand::GroupCheck(GroupCheckRequest {
+				source,
+				skill: skill.to_string(),
+				roll_mode,
+				dc,
+			})
+		}
+		"houserule" | "houserules" => BotCommand::HouseRules(HouseRulesRequest {
+			source,
+			action: parse_house_rules_action(args)?,
+		}),
+		"history" => {
+			let (count, user) = parse_history_args(args)?;
+			BotCommand::History(HistoryRequest {
+				source,
+				count,
+				user,
+			})
+		}
+		"stats" => BotCommand::Stats(StatsRequest {
+			source,
+			period: parse_stats_period(args)?,
+		}),
+		"start" | "help" => BotCommand::Help(HelpRequest {
+			source,
+			start: command.name == "start",
+		}),
+		"dm" => BotCommand::RegisterDm(RegisterDmRequest {
+			source,
+			resign: parse_dm_args(args)?,
+		}),
+		"characters" => BotCommand::ListCharacters(ListCharactersRequest { source }),
+		"character" => {
+			// the URL may be followed by a name for the character
+			let mut args = command
+				.required_args("/character https://www.dndbeyond.com/characters/12345")?
+				.splitn(2, char::is_whitespace);
+			let url = args.next().unwrap_or_default();
+			let name = args
+				.next()
+				.map(str::trim)
+				.filter(|name| !name.is_empty())
+				.map(str::to_string);
+			BotCommand::SetCharacter(SetCharacterRequest {
+				source,
+				character_id: CharacterId::try_from(url)?,
+				name,
+			})
+		}
+		"use" => BotCommand::UseCharacter(UseCharacterRequest {
+			source,
+			name: command.required_args("/use Gimli")?.to_string(),
+		}),
+		"forget" => BotCommand::ForgetCharacter(ForgetCharacterRequest {
+			source,
+			name: command.required_args("/forget Gimli")?.to_string(),
+		}),
+		"refresh" => BotCommand::Refresh(RefreshRequest { source }),
+		"init" => BotCommand::Initiative(InitiativeRequest {
+			source,
+			action: parse_initiative_action(args)?,
+		}),
+		"hp" => BotCommand::HitPoints(HitPointsRequest {
+			source,
+			action: HitPointsAction::Show,
+		}),
+		"damage" => BotCommand::HitPoints(HitPointsRequest {
+			source,
+			action: parse_hit_points_action(args, HitPointsAction::Damage)?,
+		}),
+		"heal" => BotCommand::HitPoints(HitPointsRequest {
+			source,
+			action: parse_hit_points_action(args, HitPointsAction::Heal)?,
+		}),
+		"temphp" => BotCommand::HitPoints(HitPointsRequest {
+			source,
+			action: parse_hit_points_action(args, HitPointsAction::AddTemp)?,
+		}),
+		"deathsave" => BotCommand::DeathSave(DeathSaveRequest { source }),
+		"condition" | "conditions" => BotCommand::Condition(ConditionRequest {
+			source,
+			target: reply_target,
+			action: parse_condition_action(args)?,
+		}),
+		_ => BotCommand::Unknown,
+	};
+	Ok(bot_command)
+}
+
+/// Takes the first word the parser accepts out of the command arguments, wherever it is,
+/// so that options can come in any order
+fn split_option<T>(args: &str, parse: impl Fn(&str) -> Option<T>) -> (String, Option<T>) {
+	let mut option = None;
+	let words: Vec<&str> = args
+		.split_whitespace()
+		.filter(|word| {
+			if option.is_none() {
+				option = parse(word);
+				return option.is_none();
+			}
+			true
+		})
+		.collect();
+	(words.join(" "), option)
+}
+
+/// Splits "hidden" off the command arguments, as in "/skill insight hidden adv"
+fn split_hidden(args: &str) -> (String, bool) {
+	let (args, hidden) = split_option(args, |word| {
+		if word.eq_ignore_ascii_case("hidden") {
+			Some(())
+		} else {
+			None
+		}
+	});
+	(args, hidden.is_some())
+}
+
+/// Splits a difficulty class such as "dc15" off the command arguments
+fn split_dc(args: &str) -> (String, Option<i32>) {
+	split_option(args, |word| {
+		word.get(..2)
+			.filter(|prefix| prefix.eq_ignore_ascii_case("dc"))
+			.and_then(|_| word[2..].parse().ok())
+	})
+}
+
+/// Splits "adv" or "dis" off the command arguments.
+fn split_roll_mode(args: &str) -> (String, RollMode) {
+	let (args, roll_mode) = split_option(args, |word| word.parse().ok());
+	(args, roll_mode.unwrap_or(RollMode::Normal))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CheckKind {
+	SkillCheck,
+	AbilityCheck,
+	SavingThrow,
+}
+
+/// Formats a d20 roll such as "Stealth check with advantage: 5💪+[14, 3̶]🎲 = 19",
+/// naming the condition that affected the roll if there was one
+fn format_d20_roll(
+	check: &str,
+	modifier: i32,
+	d20: i32,
+	roll_mode: RollMode,
+	discarded_d20: Option<i32>,
+	condition: Option<Condition>,
+) -> String {
+	let mut check = match roll_mode {
+		RollMode::Normal => check.to_string(),
+		RollMode::Advantage => format!("{} with advantage", check),
+		RollMode::Disadvantage => format!("{} with disadvantage", check),
+	};
+	if let Some(condition) = condition {
+		check.push_str(&format!(" ({})", condition));
+	}
+	format!(
+		"{}: {}",
+		check,
+		format_d20_dice(modifier, d20, discarded_d20)
+	)
+}
+
+/// Formats the dice of a d20 roll such as "5💪+[14, 3̶]🎲 = 19"
+fn format_d20_dice(modifier: i32, d20: i32, discarded_d20: Option<i32>) -> String {
+	let d20_text = match discarded_d20 {
+		Some(discarded) => format!("[{}, {}]", d20, dice::strikethrough(&discarded.to_string())),
+		None => d20.to_string(),
+	};
+	format!("{}💪+{}🎲 = {}", modifier, d20_text, d20 + modifier)
+}
+
+/// A history entry for a d20 roll, to be filled in with who rolled it
+fn d20_roll_record(
+	roll: String,
+	modifier: i32,
+	d20: i32,
+	discarded_d20: Option<i32>,
+) -> RollRecord {
+	RollRecord {
+		roll,
+		dice: format_d20_dice(modifier, d20, discarded_d20),
+		d20: Some(d20),
+		discarded_d20,
+		modifier,
+		total: d20 + modifier,
+		..RollRecord::default()
+	}
+}
+
+/// Marks natural 20s and 1s, which are easy to miss among the other numbers
+fn format_natural_d20(d20: i32) -> &'static str {
+	match d20 {
+		20 => ", natural 20! 🌟",
+		1 => ", natural 1! 💥",
+		_ => "",
+	}
+}
+
+/// Whether a natural 20 or 1 decides the check on its own under the critical checks house rule
+fn critical_check(critical_checks: bool, d20: i32) -> Option<bool> {
+	match d20 {
+		20 if critical_checks => Some(true),
+		1 if critical_checks => Some(false),
+		_ => None,
+	}
+}
+
+fn check_succeeds(total: i32, dc: i32, critical: Option<bool>) -> bool {
+	critical.unwrap_or(total >= dc)
+}
+
+/// Formats how much a check beat the DC by, or fell short of it
+fn format_verdict(total: i32, dc: i32, critical: Option<bool>) -> String {
+	let margin = total - dc;
+	match (check_succeeds(total, dc, critical), margin >= 0) {
+		(true, true) => format!("✅ +{}", margin),
+		(false, false) => format!("❌ {}", margin),
+		(true, false) => "✅ automatic success".to_string(),
+		(false, true) => "❌ automatic failure".to_string(),
+	}
+}
+
+/// Combines the requested roll mode with the conditions giving advantage or disadvantage,
+/// returning the condition that affected the roll
+fn apply_conditions(
+	roll_mode: RollMode,
+	advantage: Option<Condition>,
+	disadvantage: Option<Condition>,
+) -> (RollMode, Option<Condition>) {
+	let roll_mode = RollMode::new(
+		roll_mode == RollMode::Advantage || advantage.is_some(),
+		roll_mode == RollMode::Disadvantage || disadvantage.is_some(),
+	);
+	(roll_mode, disadvantage.or(advantage))
+}
+
+/// Parses "/init" arguments: "start", "next", "list", "end", "add <monster> [bonus]",
+/// or nothing but an optional "adv" or "dis" to roll for your own character
+fn parse_initiative_action(args: &str) -> Result<InitiativeAction, anyhow::Error> {
+	let args = args.trim();
+	let mut words = args.splitn(2, char::is_whitespace);
+	let action = match (words.next().unwrap_or_default(), words.next()) {
+		("", _) => InitiativeAction::Join(RollMode::Normal),
+		("start", None) => InitiativeAction::Start,
+		("next", None) => InitiativeAction::Next,
+		("list", None) => InitiativeAction::List,
+		("end", None) => InitiativeAction::End,
+		("add", Some(monster)) => {
+			let monster = monster.trim();
+			let (name, bonus) = match monster
+				.rsplitn(2, char::is_whitespace)
+				.collect::<Vec<&str>>()
+				.as_slice()
+			{
+				[bonus, name] if bonus.parse::<i32>().is_ok() => (name.trim_end(), bonus.parse()?),
+				_ => (monster, 0),
+			};
+			InitiativeAction::AddMonster {
+				name: name.to_string(),
+				bonus,
+			}
+		}
+		("add", None) => return Err(anyhow!("Expected a monster like /init add Goblin +2")),
+		(roll_mode, None) => InitiativeAction::Join(roll_mode.parse()?),
+		_ => {
+			return Err(anyhow!(
+				"Expected /init start, /init add, /init next, /init list or /init end"
+			))
+		}
+	};
+	Ok(action)
+}
+
+/// Parses "/condition" arguments: nothing to list the conditions, or "add" or "remove" and a condition
+fn parse_condition_action(args: &str) -> Result<ConditionAction, anyhow::Error> {
+	let mut words = args.trim().splitn(2, char::is_whitespace);
+	match (words.next().unwrap_or_default(), words.next()) {
+		("", None) => Ok(ConditionAction::List),
+		("add", Some(condition)) => Ok(ConditionAction::Add(condition.parse()?)),
+		("remove", Some(condition)) => Ok(ConditionAction::Remove(condition.parse()?)),
+		_ => Err(anyhow!(
+			"Expected a condition like /condition add poisoned or /condition remove prone"
+		)),
+	}
+}
+
+/// Parses "/houserule" arguments: nothing to list the house rules, or a rule and "on" or "off"
+fn parse_house_rules_action(args: &str) -> Result<HouseRulesAction, anyhow::Error> {
+	let words: Vec<&str> = args.split_whitespace().collect();
+	match words.as_slice() {
+		[] => Ok(HouseRulesAction::List),
+		[rule, "on"] => Ok(HouseRulesAction::Set(rule.parse()?, true)),
+		[rule, "off"] => Ok(HouseRulesAction::Set(rule.parse()?, false)),
+		_ => Err(anyhow!("Expected a house rule like /houserule fumbles on")),
+	}
+}
+
+const DEFAULT_HISTORY_COUNT: usize = 10;
+const MAX_HISTORY_COUNT: usize = 50;
+
+/// Parses "/history" arguments: an optional number of rolls and an optional "@user"
+fn parse_history_args(args: &str) -> Result<(usize, Option<String>), anyhow::Error> {
+	let mut count = DEFAULT_HISTORY_COUNT;
+	let mut user = None;
+	for word in args.split_whitespace() {
+		if let Ok(n) = word.parse::<usize>() {
+			count = n.clamp(1, MAX_HISTORY_COUNT);
+		} else if word.starts_with('@') {
+			user = Some(word.to_string());
+		} else {
+			return Err(anyhow!("Expected /history, /history 20 or /history @user"));
+		}
+	}
+	Ok((count, user))
+}
+
+/// Whether "/dm off" was sent rather than a plain "/dm"
+fn parse_dm_args(args: &str) -> Result<bool, anyhow::Error> {
+	match args.trim().to_lowercase().as_str() {
+		"" => Ok(false),
+		"off" => Ok(true),
+		_ => Err(anyhow!("Expected /dm or /dm off")),
+	}
+}
+
+fn parse_stats_period(args: &str) -> Result<StatsPeriod, anyhow::Error> {
+	match args.trim().to_lowercase().as_str() {
+		"" | "campaign" => Ok(StatsPeriod::Campaign),
+		"session" => Ok(StatsPeriod::Session),
+		_ => Err(anyhow!(
+			"Expected /stats, /stats session or /stats campaign"
+		)),
+	}
+}
+
+/// Parses the amount of hit points in "/damage 7", "/heal 5" or "/temphp 10"
+fn parse_hit_points_action(
+	args: &str,
+	action: fn(i32) -> HitPointsAction,
+) -> Result<HitPointsAction, anyhow::Error> {
+	match args.trim().parse::<u16>() {
+		Ok(amount) => Ok(action(amount.into())),
+		Err(_) => Err(anyhow!("Expected a number of hit points, like /damage 7")),
+	}
+}
+
 struct SkillCheckResponse {
+	kind: CheckKind,
 	skill: String,
 	modifier: i32,
 	d20: i32,
+	roll_mode: RollMode,
+	discarded_d20: Option<i32>,
+	/// The condition that forced disadvantage, or advantage
+	condition: Option<Condition>,
+	dc: Option<i32>,
+	/// Whether the chat's house rules make natural 20s and 1s decide ability checks
+	critical_checks: bool,
 }
 
 impl SkillCheckResponse {
+	fn check(&self) -> String {
+		match self.kind {
+			CheckKind::SkillCheck | CheckKind::AbilityCheck => format!("{} check", self.skill),
+			CheckKind::SavingThrow => format!("{} saving throw", self.skill),
+		}
+	}
+
+	fn roll_record(&self) -> RollRecord {
+		d20_roll_record(self.check(), self.modifier, self.d20, self.discarded_d20)
+	}
+
 	fn format(&self) -> String {
-		format!(
-			"{} check: {}💪+{}🎲 = {}",
-			self.skill,
+		let check = self.check();
+		let mut roll = format_d20_roll(
+			&check,
 			self.modifier,
 			self.d20,
-			self.d20 + self.modifier
-		)
+			self.roll_mode,
+			self.discarded_d20,
+			self.condition,
+		);
+		// Saving throws have no critical success or failure, even under the house rule
+		let critical = match self.kind {
+			CheckKind::SkillCheck | CheckKind::AbilityCheck => {
+				critical_check(self.critical_checks, self.d20)
+			}
+			CheckKind::SavingThrow => None,
+		};
+		roll.push_str(match critical {
+			Some(true) => ", critical success! 🌟",
+			Some(false) => ", critical failure! 💥",
+			None => format_natural_d20(self.d20),
+		});
+		match self.dc {
+			Some(dc) => format!(
+				"{} vs DC {} {}",
+				roll,
+				dc,
+				format_verdict(self.d20 + self.modifier, dc, critical)
+			),
+			None => roll,
+		}
 	}
 }
 
@@ -119,6 +878,132 @@ impl Display for SkillCheckResponse {
 	}
 }
 
+struct AttackResponse {
+	attack: String,
+	to_hit: Option<i32>,
+	d20: i32,
+	roll_mode: RollMode,
+	discarded_d20: Option<i32>,
+	condition: Option<Condition>,
+	damage: Option<(DiceExpression, Roll)>,
+	damage_type: Option<String>,
+	/// What went wrong on a natural 1, if the chat uses the fumble table
+	fumble: Option<&'static str>,
+}
+
+impl AttackResponse {
+	fn is_critical(&self) -> bool {
+		self.to_hit.is_some() && self.d20 == 20
+	}
+
+	fn roll_records(&self) -> Vec<RollRecord> {
+		let mut records = Vec::new();
+		if let Some(to_hit) = self.to_hit {
+			let roll = format!("{} attack", self.attack);
+			records.push(d20_roll_record(roll, to_hit, self.d20, self.discarded_d20));
+		}
+		if let Some((expression, roll)) = &self.damage {
+			records.push(RollRecord {
+				roll: format!("{} damage ({})", self.attack, expression),
+				dice: roll.to_string(),
+				total: roll.total(),
+				..RollRecord::default()
+			});
+		}
+		records
+	}
+
+	fn format(&self) -> String {
+		let mut lines = Vec::new();
+
+		if let Some(to_hit) = self.to_hit {
+			let check = format!("{} attack", self.attack);
+			let mut line = format_d20_roll(
+				&check,
+				to_hit,
+				self.d20,
+				self.roll_mode,
+				self.discarded_d20,
+				self.condition,
+			);
+			if self.is_critical() {
+				line.push_str(", critical hit! 🌟");
+			} else if self.d20 == 1 {
+				line.push_str(", critical miss! 💥");
+			}
+			lines.push(line);
+		}
+		if let Some(fumble) = self.fumble {
+			lines.push(format!("Fumble: {}", fumble));
+		}
+
+		if let Some((expression, roll)) = &self.damage {
+			let label = match self.to_hit {
+				Some(_) => "Damage".to_string(),
+				None => format!("{} damage", self.attack),
+			};
+			let dice = match &self.damage_type {
+				Some(damage_type) => format!("{} {}", expression, damage_type.to_lowercase()),
+				None => expression.to_string(),
+			};
+			lines.push(format!("{} ({}): {}", label, dice, roll));
+		}
+
+		if lines.is_empty() {
+			lines.push(format!("{} has nothing to roll.", self.attack));
+		}
+
+		lines.join("\n")
+	}
+}
+
+impl Display for AttackResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.format())
+	}
+}
+
+struct RollResponse {
+	expression: DiceExpression,
+	roll: Roll,
+}
+
+impl RollResponse {
+	fn roll_record(&self) -> RollRecord {
+		let modifier = self
+			.roll
+			.terms
+			.iter()
+			.map(|term| match term {
+				TermRoll::Constant(constant) => *constant,
+				TermRoll::Dice { .. } => 0,
+			})
+			.sum();
+		RollRecord {
+			roll: self.expression.to_string(),
+			dice: self.roll.to_string(),
+			d20: self.expression.single_d20(&self.roll),
+			modifier,
+			total: self.roll.total(),
+			..RollRecord::default()
+		}
+	}
+
+	fn format(&self) -> String {
+		let natural = match self.expression.single_d20(&self.roll) {
+			Some(d20) => format_natural_d20(d20),
+			None => "",
+		};
+		format!("{}: {}{}", self.expression, self.roll, natural)
+	}
+}
+
+impl Display for RollResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.format())
+	}
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 struct CharacterId(i64);
 
@@ -171,95 +1056,1816 @@ impl Display for CharacterId {
 /// Sample character: https://www.dndbeyond.com/characters/36535842
 const DEFAULT_CHARACTER_ID: CharacterId = CharacterId(36535842);
 
-fn telegram_user_charsheet_url(user_id: UserId) -> String {
-	format!("TELEGRAM_USER_CHARSHEET_URL {}", user_id)
-}
+/// Name the sample character is saved under when a user chooses to try it
+const DEFAULT_CHARACTER_NAME: &str = "Demo character";
+
+/// Callback data of the button that offers the sample character
+const DEMO_CHARACTER_CALLBACK: &str = "demo_character";
 
-fn character_sheet_url(character_id: CharacterId) -> Url {
-	let base = Url::parse("https://www.dndbeyond.com/characters/").unwrap();
-	base.join(&character_id.to_string()).unwrap()
+/// The user has not picked a character yet
+#[derive(Debug)]
+struct NoCharacterError;
+
+impl Display for NoCharacterError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "No character is set")
+	}
 }
 
-async fn handle_skill_check_request(
-	context: &Context,
-	request: &SkillCheckRequest,
-) -> Result<SkillCheckResponse, anyhow::Error> {
-	let mut redis_conn = context.redis.get_async_connection().await?;
+impl std::error::Error for NoCharacterError {}
 
-	let key = telegram_user_charsheet_url(request.source.user_id);
-	let character_id: Option<CharacterId> = redis_conn.get(key).await?;
-	let character_id = character_id.unwrap_or(DEFAULT_CHARACTER_ID);
+/// There are no attacks to pick from on the character sheet
+#[derive(Debug)]
+enum NoAttacksError {
+	/// The character source doesn't read the attacks table
+	NotRead,
+	Empty,
+}
 
-	let character_sheet = context
-		.headless
-		.download_character_sheet(character_sheet_url(character_id))
-		.await
-		.map_err(|_| anyhow!("Failed to download modifiers"))?;
+impl Display for NoAttacksError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			NoAttacksError::NotRead => write!(
+				f,
+				"I can't read attacks from character sheets here. Roll them with /roll, like /roll d20+5"
+			),
+			NoAttacksError::Empty => write!(f, "The character has no attacks."),
+		}
+	}
+}
 
-	let (skill, modifier) = character_sheet
-		.skills
-		.into_iter()
-		.min_by_key(|(name, _)| edit_distance(name, &request.skill))
-		.ok_or_else(|| anyhow!("Internal error: skill list is empty"))?;
+impl std::error::Error for NoAttacksError {}
 
-	let d20 = rand::thread_rng().gen_range(1..21);
+fn telegram_user_charsheet_url(user_id: UserId) -> String {
+	format!("TELEGRAM_USER_CHARSHEET_URL {}", user_id)
+}
 
-	Ok(SkillCheckResponse {
-		skill,
-		modifier,
-		d20,
-	})
+/// Hash of character names to IDs saved by the user
+fn telegram_user_characters(user_id: UserId) -> String {
+	format!("TELEGRAM_USER_CHARACTERS {}", user_id)
 }
 
-struct SetCharacterResponse;
+/// Hash of chat IDs to the character the user plays in that chat
+fn telegram_user_chat_characters(user_id: UserId) -> String {
+	format!("TELEGRAM_USER_CHAT_CHARACTERS {}", user_id)
+}
 
-impl Display for SetCharacterResponse {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		write!(f, "Will do!")
-	}
+/// Initiative order of the fight going on in the chat
+fn telegram_chat_encounter(chat_id: ChatId) -> String {
+	format!("TELEGRAM_CHAT_ENCOUNTER {}", chat_id)
 }
 
-async fn handle_set_character_request(
-	context: &Context,
-	request: &SetCharacterRequest,
-) -> Result<SetCharacterResponse, anyhow::Error> {
-	let mut redis_conn = context.redis.get_async_connection().await?;
+/// Users who have played a character in the chat
+fn telegram_chat_players(chat_id: ChatId) -> String {
+	format!("TELEGRAM_CHAT_PLAYERS {}", chat_id)
+}
 
-	let key = telegram_user_charsheet_url(request.source.user_id);
-	redis_conn.set(key, request.character_id).await?;
+fn telegram_chat_house_rules(chat_id: ChatId) -> String {
+	format!("TELEGRAM_CHAT_HOUSE_RULES {}", chat_id)
+}
 
-	Ok(SetCharacterResponse)
+/// Stream of every roll made in the chat
+fn telegram_chat_rolls(chat_id: ChatId) -> String {
+	format!("TELEGRAM_CHAT_ROLLS {}", chat_id)
 }
 
-fn response_to_string<T>(response: Result<T, anyhow::Error>) -> String
+/// The user who gets to see hidden rolls made in the chat
+fn telegram_chat_dm(chat_id: ChatId) -> String {
+	format!("TELEGRAM_CHAT_DM {}", chat_id)
+}
+
+/// Current and temporary hit points, kept across chats since it's the same character
+fn character_hit_points(character_id: CharacterId) -> String {
+	format!("CHARACTER_HIT_POINTS {}", character_id)
+}
+
+fn character_death_saves(character_id: CharacterId) -> String {
+	format!("CHARACTER_DEATH_SAVES {}", character_id)
+}
+
+fn character_conditions(character_id: CharacterId) -> String {
+	format!("CHARACTER_CONDITIONS {}", character_id)
+}
+
+fn character_sheet_cache(character_id: CharacterId) -> String {
+	format!("CHARACTER_SHEET {}", character_id)
+}
+
+/// The character bound to the chat, falling back to the user-wide default
+async fn active_character_id(
+	redis_conn: &mut redis::aio::Connection,
+	source: &RequestSource,
+) -> Result<Option<CharacterId>, anyhow::Error> {
+	let key = telegram_user_chat_characters(source.user_id);
+	let chat_character: Option<CharacterId> =
+		redis_conn.hget(key, source.chat_id.to_string()).await?;
+	let key = telegram_user_charsheet_url(source.user_id);
+	let default: Option<CharacterId> = redis_conn.get(key).await?;
+	Ok(chat_character.or(default))
+}
+
+async fn user_character_id(
+	redis_conn: &mut redis::aio::Connection,
+	source: &RequestSource,
+) -> Result<CharacterId, anyhow::Error> {
+	let character_id = active_character_id(redis_conn, source).await?;
+	let character_id = character_id.ok_or(NoCharacterError)?;
+	remember_player(redis_conn, source).await?;
+	Ok(character_id)
+}
+
+/// Remembers who plays in the group, so that the whole party can be rolled for at once
+async fn remember_player(
+	redis_conn: &mut redis::aio::Connection,
+	source: &RequestSource,
+) -> Result<(), anyhow::Error> {
+	if !source.is_private_chat() {
+		let key = telegram_chat_players(source.chat_id);
+		redis_conn.sadd(key, i64::from(source.user_id)).await?;
+	}
+	Ok(())
+}
+
+/// Where a picked character is kept
+#[derive(Debug, PartialEq, Eq)]
+enum CharacterBinding {
+	/// The user-wide default, played in every chat without a character of its own
+	Default,
+	Chat(ChatId),
+}
+
+impl CharacterBinding {
+	/// Private chats set the user-wide default, other chats get a character of their own
+	fn of(source: &RequestSource) -> CharacterBinding {
+		if source.is_private_chat() {
+			CharacterBinding::Default
+		} else {
+			CharacterBinding::Chat(source.chat_id)
+		}
+	}
+}
+
+async fn bind_character(
+	redis_conn: &mut redis::aio::Connection,
+	source: &RequestSource,
+	character_id: CharacterId,
+) -> Result<(), anyhow::Error> {
+	match CharacterBinding::of(source) {
+		CharacterBinding::Default => {
+			let key = telegram_user_charsheet_url(source.user_id);
+			redis_conn.set(key, character_id).await?;
+		}
+		CharacterBinding::Chat(chat_id) => {
+			let key = telegram_user_chat_characters(source.user_id);
+			redis_conn
+				.hset(key, chat_id.to_string(), character_id)
+				.await?;
+			remember_player(redis_conn, source).await?;
+		}
+	}
+	Ok(())
+}
+
+/// Binds the character to the chat, also making it the user-wide default
+/// if it's the first character the user picks anywhere.
+async fn play_character(
+	redis_conn: &mut redis::aio::Connection,
+	source: &RequestSource,
+	character_id: CharacterId,
+) -> Result<(), anyhow::Error> {
+	let key = telegram_user_charsheet_url(source.user_id);
+	redis_conn.set_nx(key, character_id).await?;
+	bind_character(redis_conn, source, character_id).await
+}
+
+async fn fetch_character_sheet(
+	character_source: &dyn CharacterSource,
+	character_id: CharacterId,
+) -> Result<CharacterSheet, anyhow::Error> {
+	character_source
+		.fetch(character_id)
+		.await
+		.map_err(|err| anyhow!("Failed to download character sheet: {}", err))
+}
+
+/// Downloads the character sheet and replaces the cached copy
+async fn download_character_sheet(
+	context: &Context,
+	redis_conn: &mut redis::aio::Connection,
+	character_id: CharacterId,
+) -> Result<CharacterSheet, anyhow::Error> {
+	let character_sheet = fetch_character_sheet(&*context.character_source, character_id).await?;
+
+	let key = character_sheet_cache(character_id);
+	let value = serde_json::to_string(&character_sheet)?;
+	redis_conn.set_ex(key, value, context.cache_ttl).await?;
+
+	Ok(character_sheet)
+}
+
+/// The sheet and the conditions of the character the user plays, resolved once so that
+/// both belong to the same character
+async fn load_character(
+	context: &Context,
+	source: &RequestSource,
+) -> Result<(CharacterSheet, Conditions), anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let character_id = user_character_id(&mut redis_conn, source).await?;
+	let character_sheet = cached_character_sheet(context, &mut redis_conn, character_id).await?;
+	let conditions = stored_conditions(&mut redis_conn, character_id).await?;
+	Ok((character_sheet, conditions))
+}
+
+async fn cached_character_sheet(
+	context: &Context,
+	redis_conn: &mut redis::aio::Connection,
+	character_id: CharacterId,
+) -> Result<CharacterSheet, anyhow::Error> {
+	let key = character_sheet_cache(character_id);
+	let cached: Option<String> = redis_conn.get(key).await?;
+	// A cached sheet that no longer deserializes is simply downloaded again
+	if let Some(character_sheet) = cached.and_then(|cached| serde_json::from_str(&cached).ok()) {
+		return Ok(character_sheet);
+	}
+
+	download_character_sheet(context, redis_conn, character_id).await
+}
+
+async fn load_house_rules(context: &Context, chat_id: ChatId) -> Result<HouseRules, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let house_rules: Option<HouseRules> =
+		redis_conn.get(telegram_chat_house_rules(chat_id)).await?;
+	Ok(house_rules.unwrap_or_default())
+}
+
+async fn stored_conditions(
+	redis_conn: &mut redis::aio::Connection,
+	character_id: CharacterId,
+) -> Result<Conditions, anyhow::Error> {
+	let conditions: Option<Conditions> = redis_conn.get(character_conditions(character_id)).await?;
+	Ok(conditions.unwrap_or_default())
+}
+
+fn unix_time() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|duration| duration.as_secs())
+		.unwrap_or_default()
+}
+
+/// Adds rolls made by the user to the chat's history
+async fn record_rolls(
+	context: &Context,
+	source: &RequestSource,
+	character: Option<&str>,
+	hidden: bool,
+	records: Vec<RollRecord>,
+) {
+	let timestamp = unix_time();
+	let records = records
+		.into_iter()
+		.map(|record| RollRecord {
+			user_id: source.user_id.into(),
+			user_name: source.user_name.clone(),
+			username: source.username.clone(),
+			character: character.map(str::to_string),
+			timestamp,
+			hidden,
+			..record
+		})
+		.collect();
+	append_rolls(context, source.chat_id, records).await;
+}
+
+/// Adds the rolls to the chat's history. A roll that couldn't be recorded still counts,
+/// so errors are only logged.
+async fn append_rolls(context: &Context, chat_id: ChatId, records: Vec<RollRecord>) {
+	let key = telegram_chat_rolls(chat_id);
+	let result: Result<(), anyhow::Error> = async {
+		let mut redis_conn = context.redis.get_async_connection().await?;
+		for record in records {
+			redis_conn
+				.xadd_maxlen(
+					&key,
+					StreamMaxlen::Approx(history::MAX_ROLLS),
+					"*",
+					&record.fields(),
+				)
+				.await?;
+		}
+		Ok(())
+	}
+	.await;
+	if let Err(err) = result {
+		println!("Failed to record rolls in chat {}: {}", chat_id, err);
+	}
+}
+
+/// Finds the closest ability by either its full name or its abbreviation
+fn find_ability(modifiers: HashMap<String, i32>, query: &str) -> Option<(String, i32)> {
+	let query = query.to_lowercase();
+	modifiers.into_iter().min_by_key(|(name, _)| {
+		let abbreviation = ABILITIES
+			.iter()
+			.find(|(ability, _)| ability == name)
+			.map_or(name.as_str(), |(_, abbreviation)| abbreviation);
+		edit_distance(&name.to_lowercase(), &query)
+			.min(edit_distance(&abbreviation.to_lowercase(), &query))
+	})
+}
+
+/// The skill with the name closest to the query
+fn find_skill(skills: HashMap<String, i32>, query: &str) -> Option<(String, i32)> {
+	skills
+		.into_iter()
+		.min_by_key(|(name, _)| edit_distance(name, query))
+}
+
+/// Rolls the check with the modifier from the character sheet
+fn roll_check(
+	request: &CheckRequest,
+	character_sheet: &CharacterSheet,
+	conditions: &Conditions,
+	critical_checks: bool,
+) -> Result<SkillCheckResponse, anyhow::Error> {
+	let modifiers = match request.kind {
+		CheckKind::SkillCheck => find_skill(character_sheet.skills.clone(), &request.query),
+		CheckKind::AbilityCheck => {
+			let modifiers = character_sheet
+				.abilities
+				.iter()
+				.map(|(name, ability)| (name.clone(), ability.modifier))
+				.collect();
+			find_ability(modifiers, &request.query)
+		}
+		CheckKind::SavingThrow => {
+			find_ability(character_sheet.saving_throws.clone(), &request.query)
+		}
+	};
+	let (skill, modifier) =
+		modifiers.ok_or_else(|| anyhow!("Internal error: the character sheet is empty"))?;
+
+	let disadvantage = match request.kind {
+		CheckKind::SavingThrow => conditions.saving_throw_disadvantage(&skill),
+		_ => conditions.check_disadvantage(),
+	};
+	let (roll_mode, condition) = apply_conditions(request.roll_mode, None, disadvantage);
+	let (d20, discarded_d20) = roll_mode.roll_d20(&mut rand::thread_rng());
+
+	Ok(SkillCheckResponse {
+		kind: request.kind,
+		skill,
+		modifier,
+		d20,
+		roll_mode,
+		discarded_d20,
+		condition,
+		dc: request.dc,
+		critical_checks,
+	})
+}
+
+async fn handle_check_request(
+	context: &Context,
+	request: &CheckRequest,
+) -> Result<SkillCheckResponse, anyhow::Error> {
+	let (character_sheet, conditions) = load_character(context, &request.source).await?;
+	let house_rules = load_house_rules(context, request.source.chat_id).await?;
+
+	let response = roll_check(
+		request,
+		&character_sheet,
+		&conditions,
+		house_rules.critical_checks,
+	)?;
+	let records = vec![response.roll_record()];
+	let character = Some(character_sheet.name.as_str());
+	record_rolls(context, &request.source, character, request.hidden, records).await;
+
+	Ok(response)
+}
+
+async fn handle_attack_request(
+	context: &Context,
+	request: &AttackRequest,
+) -> Result<AttackResponse, anyhow::Error> {
+	// The character service doesn't compute attack bonuses, and an empty attacks table
+	// would look like the character has none
+	if !context.character_source.reads_attacks() {
+		return Err(NoAttacksError::NotRead.into());
+	}
+	let (character_sheet, conditions) = load_character(context, &request.source).await?;
+
+	let query = request.attack.to_lowercase();
+	let Attack {
+		name,
+		to_hit,
+		damage,
+		damage_type,
+	} = character_sheet
+		.attacks
+		.into_iter()
+		.min_by_key(|attack| edit_distance(&attack.name.to_lowercase(), &query))
+		.ok_or(NoAttacksError::Empty)?;
+
+	let (roll_mode, condition) = apply_conditions(
+		request.roll_mode,
+		conditions.attack_advantage(),
+		conditions.attack_disadvantage(),
+	);
+
+	let house_rules = load_house_rules(context, request.source.chat_id).await?;
+
+	let response = {
+		let mut rng = rand::thread_rng();
+		let (d20, discarded_d20) = roll_mode.roll_d20(&mut rng);
+		let fumble = match d20 {
+			1 if to_hit.is_some() => house_rules.fumble(&mut rng),
+			_ => None,
+		};
+
+		let mut response = AttackResponse {
+			attack: name,
+			to_hit,
+			d20,
+			roll_mode,
+			discarded_d20,
+			condition,
+			damage: None,
+			damage_type,
+			fumble,
+		};
+		response.damage = damage.map(|damage| {
+			let damage = if response.is_critical() {
+				damage.critical()
+			} else {
+				damage
+			};
+			let roll = damage.roll(&mut rng);
+			(damage, roll)
+		});
+		response
+	};
+
+	let character = Some(character_sheet.name.as_str());
+	record_rolls(
+		context,
+		&request.source,
+		character,
+		false,
+		response.roll_records(),
+	)
+	.await;
+
+	Ok(response)
+}
+
+fn handle_roll_request(request: &RollRequest) -> RollResponse {
+	RollResponse {
+		expression: request.expression.clone(),
+		roll: request.expression.roll(&mut rand::thread_rng()),
+	}
+}
+
+enum SetCharacterResponse {
+	Playing(String),
+	/// Another character is already saved under the sheet's name
+	NameTaken(String),
+}
+
+impl Display for SetCharacterResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			SetCharacterResponse::Playing(name) => write!(f, "Will do! Playing as {}.", name),
+			SetCharacterResponse::NameTaken(name) => write!(
+				f,
+				"You already have another character named {}. Pick a different name with /character <url> <name>.",
+				name
+			),
+		}
+	}
+}
+
+async fn handle_set_character_request(
+	context: &Context,
+	request: &SetCharacterRequest,
+) -> Result<SetCharacterResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let key = telegram_user_characters(request.source.user_id);
+	let name = match &request.name {
+		Some(name) => {
+			redis_conn.hset(&key, name, request.character_id).await?;
+			name.clone()
+		}
+		None => {
+			let name = download_character_sheet(context, &mut redis_conn, request.character_id)
+				.await?
+				.name;
+			let saved: bool = redis_conn
+				.hset_nx(&key, &name, request.character_id)
+				.await?;
+			if !saved {
+				let existing: CharacterId = redis_conn.hget(&key, &name).await?;
+				if existing != request.character_id {
+					return Ok(SetCharacterResponse::NameTaken(name));
+				}
+			}
+			name
+		}
+	};
+
+	play_character(&mut redis_conn, &request.source, request.character_id).await?;
+
+	Ok(SetCharacterResponse::Playing(name))
+}
+
+/// Characters saved by the user by name. Users who picked their character before
+/// characters had names only have the default one, which gets saved under its name here.
+async fn saved_characters(
+	context: &Context,
+	redis_conn: &mut redis::aio::Connection,
+	user_id: UserId,
+) -> Result<HashMap<String, CharacterId>, anyhow::Error> {
+	let key = telegram_user_characters(user_id);
+	let mut characters: HashMap<String, CharacterId> = redis_conn.hgetall(&key).await?;
+
+	let default: Option<CharacterId> = redis_conn.get(telegram_user_charsheet_url(user_id)).await?;
+	if let Some(character_id) = default {
+		if !characters.values().any(|saved| *saved == character_id) {
+			let name = cached_character_sheet(context, redis_conn, character_id)
+				.await?
+				.name;
+			redis_conn.hset_nx(&key, &name, character_id).await?;
+			characters.entry(name).or_insert(character_id);
+		}
+	}
+
+	Ok(characters)
+}
+
+/// Finds a saved character by name, ignoring case
+async fn find_user_character(
+	context: &Context,
+	redis_conn: &mut redis::aio::Connection,
+	user_id: UserId,
+	name: &str,
+) -> Result<Option<(String, CharacterId)>, anyhow::Error> {
+	let characters = saved_characters(context, redis_conn, user_id).await?;
+	Ok(characters
+		.into_iter()
+		.find(|(character, _)| character.to_lowercase() == name.to_lowercase()))
+}
+
+struct UseDemoCharacterResponse;
+
+impl Display for UseDemoCharacterResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"Now playing the demo character. Use /character <url> to play your own."
+		)
+	}
+}
+
+/// Plays the sample character, only in the chat where the user chose it
+async fn handle_use_demo_character_request(
+	context: &Context,
+	request: &UseDemoCharacterRequest,
+) -> Result<UseDemoCharacterResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let key = telegram_user_characters(request.source.user_id);
+	redis_conn
+		.hset(key, DEFAULT_CHARACTER_NAME, DEFAULT_CHARACTER_ID)
+		.await?;
+	bind_character(&mut redis_conn, &request.source, DEFAULT_CHARACTER_ID).await?;
+
+	Ok(UseDemoCharacterResponse)
+}
+
+struct ListCharactersResponse {
+	characters: Vec<(String, CharacterId)>,
+	active: Option<CharacterId>,
+}
+
+impl ListCharactersResponse {
+	fn format(&self) -> String {
+		if self.characters.is_empty() {
+			return "You have no saved characters. Add one with /character <url>.".to_string();
+		}
+		let lines: Vec<String> = self
+			.characters
+			.iter()
+			.map(|(name, character_id)| {
+				if Some(*character_id) == self.active {
+					format!("{} (playing)", name)
+				} else {
+					name.clone()
+				}
+			})
+			.collect();
+		format!("Your characters:\n{}", lines.join("\n"))
+	}
+}
+
+impl Display for ListCharactersResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.format())
+	}
+}
+
+async fn handle_list_characters_request(
+	context: &Context,
+	request: &ListCharactersRequest,
+) -> Result<ListCharactersResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let characters = saved_characters(context, &mut redis_conn, request.source.user_id).await?;
+	let mut characters: Vec<(String, CharacterId)> = characters.into_iter().collect();
+	characters.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+	let active = active_character_id(&mut redis_conn, &request.source).await?;
+
+	Ok(ListCharactersResponse { characters, active })
+}
+
+enum UseCharacterResponse {
+	Playing(String),
+	NotFound(String),
+}
+
+impl Display for UseCharacterResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			UseCharacterResponse::Playing(name) => write!(f, "Now playing as {}.", name),
+			UseCharacterResponse::NotFound(name) => write!(
+				f,
+				"You have no character named \"{}\". See /characters for the list.",
+				name
+			),
+		}
+	}
+}
+
+async fn handle_use_character_request(
+	context: &Context,
+	request: &UseCharacterRequest,
+) -> Result<UseCharacterResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let user_id = request.source.user_id;
+	let (name, character_id) =
+		match find_user_character(context, &mut redis_conn, user_id, &request.name).await? {
+			Some(character) => character,
+			None => return Ok(UseCharacterResponse::NotFound(request.name.clone())),
+		};
+
+	play_character(&mut redis_conn, &request.source, character_id).await?;
+
+	Ok(UseCharacterResponse::Playing(name))
+}
+
+enum ForgetCharacterResponse {
+	Forgotten(String),
+	NotFound(String),
+}
+
+impl Display for ForgetCharacterResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ForgetCharacterResponse::Forgotten(name) => write!(f, "Forgot {}.", name),
+			ForgetCharacterResponse::NotFound(name) => write!(
+				f,
+				"You have no character named \"{}\". See /characters for the list.",
+				name
+			),
+		}
+	}
+}
+
+async fn handle_forget_character_request(
+	context: &Context,
+	request: &ForgetCharacterRequest,
+) -> Result<ForgetCharacterResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let user_id = request.source.user_id;
+	let (name, character_id) =
+		match find_user_character(context, &mut redis_conn, user_id, &request.name).await? {
+			Some(character) => character,
+			None => return Ok(ForgetCharacterResponse::NotFound(request.name.clone())),
+		};
+
+	let key = telegram_user_characters(user_id);
+	redis_conn.hdel(key, &name).await?;
+
+	// Stop playing the forgotten character, both by default and in every chat
+	let key = telegram_user_charsheet_url(user_id);
+	let active: Option<CharacterId> = redis_conn.get(&key).await?;
+	if active == Some(character_id) {
+		redis_conn.del(&key).await?;
+	}
+
+	let key = telegram_user_chat_characters(user_id);
+	let chat_characters: HashMap<String, CharacterId> = redis_conn.hgetall(&key).await?;
+	for (chat, chat_character_id) in chat_characters {
+		if chat_character_id == character_id {
+			redis_conn.hdel(&key, chat).await?;
+		}
+	}
+
+	Ok(ForgetCharacterResponse::Forgotten(name))
+}
+
+enum InitiativeResponse {
+	Started,
+	Rolled {
+		name: String,
+		bonus: i32,
+		d20: i32,
+		roll_mode: RollMode,
+		discarded_d20: Option<i32>,
+		condition: Option<Condition>,
+	},
+	Turn {
+		round: u32,
+		name: String,
+	},
+	Order(Encounter),
+	Ended,
+	/// Another user's encounter is still running
+	AlreadyStarted,
+	AlreadyRolled(String),
+	NoEncounter,
+	NobodyRolled,
+	NotAllowed,
+}
+
+impl Display for InitiativeResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			InitiativeResponse::Started => {
+				write!(f, "⚔️ Roll for initiative! Everyone send /init.")
+			}
+			InitiativeResponse::Rolled {
+				name,
+				bonus,
+				d20,
+				roll_mode,
+				discarded_d20,
+				condition,
+			} => {
+				let roll = format_d20_roll(
+					&format!("{} initiative", name),
+					*bonus,
+					*d20,
+					*roll_mode,
+					*discarded_d20,
+					*condition,
+				);
+				write!(f, "{}{}", roll, format_natural_d20(*d20))
+			}
+			InitiativeResponse::Turn { round, name } => {
+				write!(f, "Round {}: {}'s turn!", round, name)
+			}
+			InitiativeResponse::Order(encounter) => encounter.fmt(f),
+			InitiativeResponse::Ended => write!(f, "The encounter is over."),
+			InitiativeResponse::NoEncounter => {
+				write!(
+					f,
+					"There is no encounter in this chat. Start one with /init start."
+				)
+			}
+			InitiativeResponse::NobodyRolled => write!(f, "Nobody has rolled initiative yet."),
+			InitiativeResponse::NotAllowed => {
+				write!(f, "Only the DM who started the encounter can do that.")
+			}
+			InitiativeResponse::AlreadyStarted => write!(
+				f,
+				"An encounter is already running. Only its DM can start a new one or /init end it."
+			),
+			InitiativeResponse::AlreadyRolled(name) => {
+				write!(f, "{} has already rolled initiative.", name)
+			}
+		}
+	}
+}
+
+async fn handle_initiative_request(
+	context: &Context,
+	request: &InitiativeRequest,
+) -> Result<InitiativeResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let source = &request.source;
+	let key = telegram_chat_encounter(source.chat_id);
+
+	let stored: Option<Encounter> = redis_conn.get(&key).await?;
+	let mut encounter = match (stored, &request.action) {
+		(Some(encounter), InitiativeAction::Start) if encounter.dm != source.user_id => {
+			return Ok(InitiativeResponse::AlreadyStarted)
+		}
+		(_, InitiativeAction::Start) => Encounter::new(source.user_id),
+		(Some(encounter), _) => encounter,
+		(None, _) => return Ok(InitiativeResponse::NoEncounter),
+	};
+	let is_dm = encounter.dm == source.user_id;
+
+	let response = match &request.action {
+		InitiativeAction::Start => InitiativeResponse::Started,
+		InitiativeAction::Join(roll_mode) => {
+			// Rolling again until the dice are kind isn't allowed
+			if let Some(combatant) = encounter.find_player(source.user_id) {
+				return Ok(InitiativeResponse::AlreadyRolled(combatant.name.clone()));
+			}
+			// Initiative is a Dexterity check, so the same conditions apply
+			let (character_sheet, conditions) = load_character(context, source).await?;
+			let (roll_mode, condition) =
+				apply_conditions(*roll_mode, None, conditions.check_disadvantage());
+			let (d20, discarded_d20) = roll_mode.roll_d20(&mut rand::thread_rng());
+			let combatant = encounter.add(Combatant {
+				name: character_sheet.name,
+				player: Some(source.user_id),
+				bonus: character_sheet.initiative,
+				d20,
+			});
+			InitiativeResponse::Rolled {
+				name: combatant.name.clone(),
+				bonus: combatant.bonus,
+				d20,
+				roll_mode,
+				discarded_d20,
+				condition,
+			}
+		}
+		InitiativeAction::AddMonster { .. } | InitiativeAction::End if !is_dm => {
+			return Ok(InitiativeResponse::NotAllowed)
+		}
+		InitiativeAction::AddMonster { name, bonus } => {
+			let d20 = dice::roll_die(&mut rand::thread_rng(), 20);
+			let combatant = encounter.add(Combatant {
+				name: name.clone(),
+				player: None,
+				bonus: *bonus,
+				d20,
+			});
+			InitiativeResponse::Rolled {
+				name: combatant.name.clone(),
+				bonus: combatant.bonus,
+				d20,
+				roll_mode: RollMode::Normal,
+				discarded_d20: None,
+				condition: None,
+			}
+		}
+		InitiativeAction::Next => {
+			// Players may end their own turn, everything else is up to the DM
+			let current_player = encounter.current.as_ref().and_then(|current| {
+				encounter
+					.combatants
+					.iter()
+					.find(|combatant| &combatant.name == current)
+					.and_then(|combatant| combatant.player)
+			});
+			if !is_dm && current_player != Some(source.user_id) {
+				return Ok(InitiativeResponse::NotAllowed);
+			}
+			match encounter.next_turn() {
+				Some(combatant) => InitiativeResponse::Turn {
+					name: combatant.name.clone(),
+					round: encounter.round,
+				},
+				None => return Ok(InitiativeResponse::NobodyRolled),
+			}
+		}
+		InitiativeAction::List => return Ok(InitiativeResponse::Order(encounter)),
+		InitiativeAction::End => {
+			redis_conn.del(&key).await?;
+			return Ok(InitiativeResponse::Ended);
+		}
+	};
+
+	redis_conn.set(&key, encounter).await?;
+
+	if let InitiativeResponse::Rolled {
+		name,
+		bonus,
+		d20,
+		discarded_d20,
+		..
+	} = &response
+	{
+		let record = d20_roll_record("Initiative".to_string(), *bonus, *d20, *discarded_d20);
+		record_rolls(context, source, Some(name), false, vec![record]).await;
+	}
+
+	Ok(response)
+}
+
+struct HitPointsResponse {
+	name: String,
+	action: HitPointsAction,
+	hit_points: HitPoints,
+}
+
+impl HitPointsResponse {
+	fn format(&self) -> String {
+		let name = &self.name;
+		let hit_points = &self.hit_points;
+		match self.action {
+			HitPointsAction::Show => format!("{}: {}", name, hit_points),
+			HitPointsAction::Damage(_) if hit_points.is_down() => {
+				format!("{} drops to 0 HP and falls unconscious! 💀", name)
+			}
+			HitPointsAction::Damage(amount) => {
+				format!("{} takes {} damage: {}", name, amount, hit_points)
+			}
+			HitPointsAction::Heal(amount) => {
+				format!("{} regains {} HP: {}", name, amount, hit_points)
+			}
+			HitPointsAction::AddTemp(_) => {
+				format!(
+					"{} has {} temporary HP: {}",
+					name, hit_points.temp, hit_points
+				)
+			}
+		}
+	}
+}
+
+impl Display for HitPointsResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.format())
+	}
+}
+
+/// Characters start with full hit points until the bot hears otherwise
+async fn stored_hit_points(
+	redis_conn: &mut redis::aio::Connection,
+	character_id: CharacterId,
+	max_hit_points: i32,
+) -> Result<HitPoints, anyhow::Error> {
+	let stored: Option<HitPoints> = redis_conn.get(character_hit_points(character_id)).await?;
+	Ok(match stored {
+		Some(hit_points) => hit_points.with_max(max_hit_points),
+		None => HitPoints::new(max_hit_points),
+	})
+}
+
+async fn handle_hit_points_request(
+	context: &Context,
+	request: &HitPointsRequest,
+) -> Result<HitPointsResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let character_id = user_character_id(&mut redis_conn, &request.source).await?;
+	let character_sheet = cached_character_sheet(context, &mut redis_conn, character_id).await?;
+
+	let key = character_hit_points(character_id);
+	let mut hit_points = stored_hit_points(
+		&mut redis_conn,
+		character_id,
+		character_sheet.max_hit_points,
+	)
+	.await?;
+
+	match request.action {
+		HitPointsAction::Show => {}
+		HitPointsAction::Damage(amount) => hit_points.damage(amount),
+		HitPointsAction::Heal(amount) => {
+			hit_points.heal(amount);
+			redis_conn.del(character_death_saves(character_id)).await?;
+		}
+		HitPointsAction::AddTemp(amount) => hit_points.add_temp(amount),
+	}
+	redis_conn.set(&key, hit_points).await?;
+
+	Ok(HitPointsResponse {
+		name: character_sheet.name,
+		action: request.action,
+		hit_points,
+	})
+}
+
+enum DeathSaveResponse {
+	Rolled {
+		name: String,
+		d20: i32,
+		result: DeathSave,
+		death_saves: DeathSaves,
+	},
+	NotDying(String),
+	AlreadyStable(String),
+	AlreadyDead(String),
+}
+
+impl Display for DeathSaveResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			DeathSaveResponse::Rolled {
+				name,
+				d20,
+				result,
+				death_saves,
+			} => {
+				write!(f, "{} death saving throw: [{}]🎲", name, d20)?;
+				match result {
+					DeathSave::Success | DeathSave::Failure => write!(
+						f,
+						"\nSuccesses: {}, failures: {}",
+						death_saves.successes, death_saves.failures
+					),
+					DeathSave::Stable => write!(f, "\n{} is stable.", name),
+					DeathSave::Dead => write!(f, "\n{} dies. 💀", name),
+					DeathSave::Revived => write!(f, "\n{} regains 1 HP and wakes up!", name),
+				}
+			}
+			DeathSaveResponse::NotDying(name) => write!(f, "{} is not dying.", name),
+			DeathSaveResponse::AlreadyStable(name) => {
+				write!(f, "{} is already stable. Heal them with /heal.", name)
+			}
+			DeathSaveResponse::AlreadyDead(name) => write!(f, "{} is dead. 💀", name),
+		}
+	}
+}
+
+async fn handle_death_save_request(
+	context: &Context,
+	request: &DeathSaveRequest,
+) -> Result<DeathSaveResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let character_id = user_character_id(&mut redis_conn, &request.source).await?;
+	let character_sheet = cached_character_sheet(context, &mut redis_conn, character_id).await?;
+	let name = character_sheet.name;
+
+	let mut hit_points = stored_hit_points(
+		&mut redis_conn,
+		character_id,
+		character_sheet.max_hit_points,
+	)
+	.await?;
+	if !hit_points.is_down() {
+		return Ok(DeathSaveResponse::NotDying(format!(
+			"{} ({})",
+			name, hit_points
+		)));
+	}
+
+	let key = character_death_saves(character_id);
+	let stored: Option<DeathSaves> = redis_conn.get(&key).await?;
+	let mut death_saves = stored.unwrap_or_default();
+	if death_saves.is_dead() {
+		return Ok(DeathSaveResponse::AlreadyDead(name));
+	}
+	if death_saves.is_stable() {
+		return Ok(DeathSaveResponse::AlreadyStable(name));
+	}
+
+	let d20 = dice::roll_die(&mut rand::thread_rng(), 20);
+	let result = death_saves.record(d20);
+	if let DeathSave::Revived = result {
+		hit_points.current = 1;
+		redis_conn
+			.set(character_hit_points(character_id), hit_points)
+			.await?;
+		redis_conn.del(&key).await?;
+	} else {
+		redis_conn.set(&key, death_saves).await?;
+	}
+
+	let record = d20_roll_record("Death saving throw".to_string(), 0, d20, None);
+	record_rolls(context, &request.source, Some(&name), false, vec![record]).await;
+
+	Ok(DeathSaveResponse::Rolled {
+		name,
+		d20,
+		result,
+		death_saves,
+	})
+}
+
+enum ConditionResponse {
+	List {
+		name: String,
+		conditions: Conditions,
+	},
+	Added {
+		name: String,
+		condition: Condition,
+	},
+	Removed {
+		name: String,
+		condition: Condition,
+	},
+	NotAffected {
+		name: String,
+		condition: Condition,
+	},
+	NoTargetCharacter,
+	NotDm,
+}
+
+impl Display for ConditionResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ConditionResponse::List { name, conditions } if conditions.conditions.is_empty() => {
+				write!(f, "{} has no conditions.", name)
+			}
+			ConditionResponse::List { name, conditions } => write!(f, "{}: {}", name, conditions),
+			ConditionResponse::Added { name, condition } => {
+				write!(f, "{} is now {}.", name, condition)
+			}
+			ConditionResponse::Removed { name, condition } => {
+				write!(f, "{} is no longer {}.", name, condition)
+			}
+			ConditionResponse::NotAffected { name, condition } => {
+				write!(f, "{} is not {}.", name, condition)
+			}
+			ConditionResponse::NoTargetCharacter => {
+				write!(f, "That player hasn't picked a character in this chat.")
+			}
+			ConditionResponse::NotDm => write!(
+				f,
+				"Only the DM can change the conditions of other players' characters."
+			),
+		}
+	}
+}
+
+async fn handle_condition_request(
+	context: &Context,
+	request: &ConditionRequest,
+) -> Result<ConditionResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	// The DM can reply to a player's message to apply the condition to their character instead
+	let character_id = match request.target {
+		Some(user_id) if user_id != request.source.user_id => {
+			let dm: Option<i64> = redis_conn
+				.get(telegram_chat_dm(request.source.chat_id))
+				.await?;
+			if dm != Some(i64::from(request.source.user_id)) {
+				return Ok(ConditionResponse::NotDm);
+			}
+			let target = RequestSource {
+				user_id,
+				..request.source.clone()
+			};
+			match active_character_id(&mut redis_conn, &target).await? {
+				Some(character_id) => character_id,
+				None => return Ok(ConditionResponse::NoTargetCharacter),
+			}
+		}
+		_ => user_character_id(&mut redis_conn, &request.source).await?,
+	};
+	let name = cached_character_sheet(context, &mut redis_conn, character_id)
+		.await?
+		.name;
+
+	let key = character_conditions(character_id);
+	let stored: Option<Conditions> = redis_conn.get(&key).await?;
+	let mut conditions = stored.unwrap_or_default();
+
+	let response = match request.action {
+		ConditionAction::List => return Ok(ConditionResponse::List { name, conditions }),
+		ConditionAction::Add(condition) => {
+			conditions.add(condition);
+			ConditionResponse::Added { name, condition }
+		}
+		ConditionAction::Remove(condition) if conditions.remove(condition) => {
+			ConditionResponse::Removed { name, condition }
+		}
+		ConditionAction::Remove(condition) => {
+			return Ok(ConditionResponse::NotAffected { name, condition })
+		}
+	};
+	redis_conn.set(&key, conditions).await?;
+
+	Ok(response)
+}
+
+struct GroupCheckRoll {
+	name: String,
+	modifier: i32,
+	d20: i32,
+	roll_mode: RollMode,
+	discarded_d20: Option<i32>,
+	condition: Option<Condition>,
+}
+
+impl GroupCheckRoll {
+	fn total(&self) -> i32 {
+		self.d20 + self.modifier
+	}
+}
+
+struct GroupCheckResponse {
+	skill: String,
+	dc: Option<i32>,
+	rolls: Vec<GroupCheckRoll>,
+	critical_checks: bool,
+}
+
+impl GroupCheckResponse {
+	/// How many members beat the DC, if there is one
+	fn successes(&self) -> Option<usize> {
+		let dc = self.dc?;
+		let successes = self.rolls.iter().filter(|roll| {
+			check_succeeds(
+				roll.total(),
+				dc,
+				critical_check(self.critical_checks, roll.d20),
+			)
+		});
+		Some(successes.count())
+	}
+
+	fn format(&self) -> String {
+		if self.rolls.is_empty() {
+			return "Nobody in this chat has picked a character yet.".to_string();
+		}
+
+		let mut lines = vec![match self.dc {
+			Some(dc) => format!("{} group check, DC {}:", self.skill, dc),
+			None => format!("{} group check:", self.skill),
+		}];
+		for roll in &self.rolls {
+			let mut line = format_d20_roll(
+				&roll.name,
+				roll.modifier,
+				roll.d20,
+				roll.roll_mode,
+				roll.discarded_d20,
+				roll.condition,
+			);
+			line.push_str(format_natural_d20(roll.d20));
+			if let Some(dc) = self.dc {
+				let critical = critical_check(self.critical_checks, roll.d20);
+				line.push_str(&format!(" {}", format_verdict(roll.total(), dc, critical)));
+			}
+			lines.push(line);
+		}
+		if let Some(successes) = self.successes() {
+			// The group succeeds if at least half of its members succeed
+			let verdict = if successes * 2 >= self.rolls.len() {
+				"the group succeeds! ✅"
+			} else {
+				"the group fails. ❌"
+			};
+			lines.push(format!(
+				"{} of {} succeeded, {}",
+				successes,
+				self.rolls.len(),
+				verdict
+			));
+		}
+		lines.join("\n")
+	}
+}
+
+impl Display for GroupCheckResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.format())
+	}
+}
+
+async fn handle_group_check_request(
+	context: &Context,
+	request: &GroupCheckRequest,
+) -> Result<GroupCheckResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let key = telegram_chat_players(request.source.chat_id);
+	let players: Vec<i64> = redis_conn.smembers(key).await?;
+
+	let mut rolls = Vec::new();
+	let mut records = Vec::new();
+	let mut skill_name = request.skill.clone();
+	for player in players {
+		let player_source = RequestSource {
+			user_id: UserId::new(player),
+			..request.source.clone()
+		};
+		let character_id = match active_character_id(&mut redis_conn, &player_source).await? {
+			Some(character_id) => character_id,
+			None => continue,
+		};
+		// One unavailable character sheet shouldn't spoil the roll for the whole party
+		let character_sheet =
+			match cached_character_sheet(context, &mut redis_conn, character_id).await {
+				Ok(character_sheet) => character_sheet,
+				Err(err) => {
+					println!(
+						"Skipping character {} in group check: {}",
+						character_id, err
+					);
+					continue;
+				}
+			};
+
+		let (skill, modifier) = find_skill(character_sheet.skills, &request.skill)
+			.ok_or_else(|| anyhow!("Internal error: skill list is empty"))?;
+		skill_name = skill;
+
+		let conditions = stored_conditions(&mut redis_conn, character_id).await?;
+		let (roll_mode, condition) =
+			apply_conditions(request.roll_mode, None, conditions.check_disadvantage());
+		let (d20, discarded_d20) = roll_mode.roll_d20(&mut rand::thread_rng());
+
+		let roll = format!("{} check", skill_name);
+		records.push(RollRecord {
+			user_id: player,
+			character: Some(character_sheet.name.clone()),
+			timestamp: unix_time(),
+			..d20_roll_record(roll, modifier, d20, discarded_d20)
+		});
+		rolls.push(GroupCheckRoll {
+			name: character_sheet.name,
+			modifier,
+			d20,
+			roll_mode,
+			discarded_d20,
+			condition,
+		});
+	}
+	rolls.sort_by_key(|roll| -roll.total());
+	append_rolls(context, request.source.chat_id, records).await;
+
+	let house_rules = load_house_rules(context, request.source.chat_id).await?;
+
+	Ok(GroupCheckResponse {
+		skill: skill_name,
+		dc: request.dc,
+		rolls,
+		critical_checks: house_rules.critical_checks,
+	})
+}
+
+enum HouseRulesResponse {
+	List(HouseRules),
+	Changed(HouseRules),
+	NotAllowed,
+}
+
+impl Display for HouseRulesResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			HouseRulesResponse::List(house_rules) => {
+				write!(f, "House rules in this chat:\n{}", house_rules)
+			}
+			HouseRulesResponse::Changed(house_rules) => {
+				write!(f, "House rules updated:\n{}", house_rules)
+			}
+			HouseRulesResponse::NotAllowed => write!(f, "Only the DM can change the house rules."),
+		}
+	}
+}
+
+async fn handle_house_rules_request(
+	context: &Context,
+	request: &HouseRulesRequest,
+) -> Result<HouseRulesResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let source = &request.source;
+	let key = telegram_chat_house_rules(source.chat_id);
+	let stored: Option<HouseRules> = redis_conn.get(&key).await?;
+	let mut house_rules = stored.unwrap_or_default();
+
+	let (rule, on) = match request.action {
+		HouseRulesAction::List => return Ok(HouseRulesResponse::List(house_rules)),
+		HouseRulesAction::Set(rule, on) => (rule, on),
+	};
+
+	// Anyone may set the rules until the group has a DM
+	let dm: Option<i64> = redis_conn.get(telegram_chat_dm(source.chat_id)).await?;
+	if matches!(dm, Some(dm) if dm != i64::from(source.user_id)) {
+		return Ok(HouseRulesResponse::NotAllowed);
+	}
+
+	house_rules.set(rule, on);
+	redis_conn.set(&key, house_rules).await?;
+
+	Ok(HouseRulesResponse::Changed(house_rules))
+}
+
+/// Rolls this recent count towards the current session
+const SESSION_SECONDS: u64 = 12 * 60 * 60;
+
+struct StatsResponse {
+	period: StatsPeriod,
+	players: Vec<PlayerStats>,
+}
+
+impl Display for StatsResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let period = match self.period {
+			StatsPeriod::Session => "this session",
+			StatsPeriod::Campaign => "this campaign",
+		};
+		if self.players.is_empty() {
+			return write!(f, "No d20 rolls {} yet.", period);
+		}
+		let players: Vec<String> = self
+			.players
+			.iter()
+			.map(|player| player.d20.format(&player.name))
+			.collect();
+		write!(f, "d20 rolls {}:\n\n{}", period, players.join("\n\n"))
+	}
+}
+
+async fn handle_stats_request(
+	context: &Context,
+	request: &StatsRequest,
+) -> Result<StatsResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let key = telegram_chat_rolls(request.source.chat_id);
+	let reply: StreamRangeReply = match request.period {
+		StatsPeriod::Session => {
+			// Stream entry ids start with the time they were added in milliseconds
+			let start = unix_time().saturating_sub(SESSION_SECONDS) * 1000;
+			redis_conn.xrange(&key, start, "+").await?
+		}
+		StatsPeriod::Campaign => redis_conn.xrange_all(&key).await?,
+	};
+	let rolls: Vec<RollRecord> = reply
+		.ids
+		.iter()
+		.filter_map(RollRecord::from_stream_id)
+		.collect();
+
+	Ok(StatsResponse {
+		period: request.period,
+		players: stats::player_stats(&rolls),
+	})
+}
+
+struct HelpResponse {
+	start: bool,
+}
+
+impl Display for HelpResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if self.start {
+			writeln!(
+				f,
+				"Hi! I roll dice for your D&D Beyond characters. Start by sending me a link to \
+				your character sheet like this: /character https://www.dndbeyond.com/characters/12345678\n"
+			)?;
+		}
+		write!(f, "Here's what I can do:")?;
+		for (command, description) in COMMANDS.iter() {
+			write!(f, "\n/{} - {}", command, description)?;
+		}
+		Ok(())
+	}
+}
+
+struct HistoryResponse {
+	/// Rolls from the oldest to the newest
+	rolls: Vec<RollRecord>,
+	now: u64,
+}
+
+impl Display for HistoryResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if self.rolls.is_empty() {
+			return write!(f, "No rolls yet.");
+		}
+		let lines: Vec<String> = self
+			.rolls
+			.iter()
+			.map(|roll| roll.format(self.now))
+			.collect();
+		f.write_str(&lines.join("\n"))
+	}
+}
+
+/// How far back to look for rolls by a particular user
+const HISTORY_SEARCH_DEPTH: usize = 1000;
+
+async fn handle_history_request(
+	context: &Context,
+	request: &HistoryRequest,
+) -> Result<HistoryResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let key = telegram_chat_rolls(request.source.chat_id);
+	let depth = match request.user {
+		Some(_) => HISTORY_SEARCH_DEPTH,
+		None => request.count,
+	};
+	let reply: StreamRangeReply = redis_conn.xrevrange_count(&key, "+", "-", depth).await?;
+
+	let mut rolls: Vec<RollRecord> = reply
+		.ids
+		.iter()
+		.filter_map(RollRecord::from_stream_id)
+		.filter(|roll| match &request.user {
+			Some(user) => roll.is_by(user),
+			None => true,
+		})
+		.take(request.count)
+		.collect();
+	rolls.reverse();
+
+	Ok(HistoryResponse {
+		rolls,
+		now: unix_time(),
+	})
+}
+
+enum RegisterDmResponse {
+	Registered(String),
+	/// Somebody else is already the DM
+	Taken,
+	Resigned(String),
+	/// Only the DM can give the role up
+	NotDm,
+	PrivateChat,
+}
+
+impl Display for RegisterDmResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RegisterDmResponse::Registered(name) => write!(
+				f,
+				"{} is the DM now and will see hidden rolls. Make sure you've started a private chat with me.",
+				name
+			),
+			RegisterDmResponse::Taken => write!(
+				f,
+				"This chat already has a DM. They can hand it over with /dm off."
+			),
+			RegisterDmResponse::Resigned(name) => write!(
+				f,
+				"{} is no longer the DM. Anyone can send /dm to take over.",
+				name
+			),
+			RegisterDmResponse::NotDm => write!(f, "Only the DM can step down."),
+			RegisterDmResponse::PrivateChat => write!(f, "Send /dm in the group you're running."),
+		}
+	}
+}
+
+async fn handle_register_dm_request(
+	context: &Context,
+	request: &RegisterDmRequest,
+) -> Result<RegisterDmResponse, anyhow::Error> {
+	let source = &request.source;
+	if source.is_private_chat() {
+		return Ok(RegisterDmResponse::PrivateChat);
+	}
+
+	let mut redis_conn = context.redis.get_async_connection().await?;
+	let key = telegram_chat_dm(source.chat_id);
+	let user_id = i64::from(source.user_id);
+
+	if request.resign {
+		let dm: Option<i64> = redis_conn.get(&key).await?;
+		if dm != Some(user_id) {
+			return Ok(RegisterDmResponse::NotDm);
+		}
+		redis_conn.del(&key).await?;
+		return Ok(RegisterDmResponse::Resigned(source.user_name.clone()));
+	}
+
+	// The DM sees every hidden roll, so the first one to claim the chat keeps it
+	// until they step down
+	let registered: bool = redis_conn.set_nx(&key, user_id).await?;
+	if !registered {
+		let dm: i64 = redis_conn.get(&key).await?;
+		if dm != user_id {
+			return Ok(RegisterDmResponse::Taken);
+		}
+	}
+
+	Ok(RegisterDmResponse::Registered(source.user_name.clone()))
+}
+
+/// Sends the reply of a hidden roll to the roller and the DM, leaving only a note in the group
+async fn send_secretly(
+	context: &Context,
+	token: &str,
+	source: &RequestSource,
+	reply: Reply,
+) -> Result<Reply, anyhow::Error> {
+	if source.is_private_chat() {
+		return Ok(reply);
+	}
+
+	let mut redis_conn = context.redis.get_async_connection().await?;
+	let dm: Option<i64> = redis_conn.get(telegram_chat_dm(source.chat_id)).await?;
+	let dm =
+		match dm {
+			Some(dm) => UserId::new(dm),
+			None => return Ok(
+				"Nobody is the DM in this chat yet, so I can't roll secretly. The DM can send /dm."
+					.to_string()
+					.into(),
+			),
+		};
+
+	let mut notes = vec!["🎲 rolled secretly".to_string()];
+	if !source.respond_privately(token, &reply).await {
+		notes.push(format!(
+			"{}, I can't message you privately. Open a chat with me and press Start to see your secret rolls.",
+			source.user_name
+		));
+	}
+	if dm != source.user_id {
+		let dm_source = RequestSource {
+			user_id: dm,
+			..source.clone()
+		};
+		let dm_reply = Reply::from(format!(
+			"{} rolled secretly:\n{}",
+			source.user_name, reply.text
+		));
+		if !dm_source.respond_privately(token, &dm_reply).await {
+			notes.push(
+				"The DM hasn't opened a private chat with me, so they didn't see the roll either."
+					.to_string(),
+			);
+		}
+	}
+
+	Ok(notes.join("\n").into())
+}
+
+struct RefreshResponse;
+
+impl Display for RefreshResponse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Character sheet refreshed!")
+	}
+}
+
+async fn handle_refresh_request(
+	context: &Context,
+	request: &RefreshRequest,
+) -> Result<RefreshResponse, anyhow::Error> {
+	let mut redis_conn = context.redis.get_async_connection().await?;
+
+	let character_id = user_character_id(&mut redis_conn, &request.source).await?;
+	download_character_sheet(context, &mut redis_conn, character_id).await?;
+
+	Ok(RefreshResponse)
+}
+
+/// Explains how to set a character and offers to try the sample one
+fn no_character_reply() -> Reply {
+	let button = InlineKeyboardButton::callback("Try the demo character", DEMO_CHARACTER_CALLBACK);
+	Reply {
+		text: "I don't know your character yet. Send me a link to your D&D Beyond character sheet \
+			like this: /character https://www.dndbeyond.com/characters/12345678"
+			.to_string(),
+		keyboard: Some(vec![vec![button]].into()),
+	}
+}
+
+fn response_to_reply<T>(response: Result<T, anyhow::Error>) -> Reply
 where
 	T: Display,
 {
 	match response {
-		Ok(ok) => ok.to_string(),
+		Ok(ok) => ok.to_string().into(),
+		Err(err) if err.is::<NoCharacterError>() => no_character_reply(),
+		Err(err) if err.is::<NoAttacksError>() => err.to_string().into(),
 		Err(err) => {
 			println!("Internal error: {}", err);
-			"Sorry, boss, I can't do that.".to_string()
+			"Sorry, boss, I can't do that.".to_string().into()
 		}
 	}
 }
 
+/// Turns the response into a reply, sending it secretly if the roll is hidden
+async fn hide_reply<T>(
+	context: &Context,
+	token: &str,
+	source: &RequestSource,
+	hidden: bool,
+	response: Result<T, anyhow::Error>,
+) -> Reply
+where
+	T: Display,
+{
+	let reply = response_to_reply(response);
+	// Asking the user to pick a character is no secret
+	if !hidden || reply.keyboard.is_some() {
+		return reply;
+	}
+	match send_secretly(context, token, source, reply).await {
+		Ok(reply) => reply,
+		Err(err) => response_to_reply::<String>(Err(err)),
+	}
+}
+
 async fn handle_update(context: &Context, token: &str, update: Update) {
-	let response = match update.into() {
-		BotCommand::SkillCheck(request) => {
-			let response = handle_skill_check_request(context, &request).await;
-			Some((request.source, response_to_string(response)))
+	let bot_username = context.bot_username(token).await;
+	let response = match BotCommand::from_update(update, bot_username.as_deref()) {
+		BotCommand::Check(request) => {
+			let response = handle_check_request(context, &request).await;
+			let reply = hide_reply(context, token, &request.source, request.hidden, response).await;
+			Some((request.source, reply))
+		}
+		BotCommand::Attack(request) => {
+			let response = handle_attack_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::Roll(request) => {
+			let response = handle_roll_request(&request);
+			let records = vec![response.roll_record()];
+			record_rolls(context, &request.source, None, request.hidden, records).await;
+			let reply = hide_reply(
+				context,
+				token,
+				&request.source,
+				request.hidden,
+				Ok(response),
+			)
+			.await;
+			Some((request.source, reply))
 		}
 		BotCommand::SetCharacter(request) => {
 			let response = handle_set_character_request(context, &request).await;
-			Some((request.source, response_to_string(response)))
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::UseDemoCharacter(request) => {
+			let response = handle_use_demo_character_request(context, &request).await;
+			telegram::answer_callback_query(token, &request.callback_query_id).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::ListCharacters(request) => {
+			let response = handle_list_characters_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::UseCharacter(request) => {
+			let response = handle_use_character_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::ForgetCharacter(request) => {
+			let response = handle_forget_character_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::Initiative(request) => {
+			let response = handle_initiative_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::HitPoints(request) => {
+			let response = handle_hit_points_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::DeathSave(request) => {
+			let response = handle_death_save_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::Condition(request) => {
+			let response = handle_condition_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::GroupCheck(request) => {
+			let response = handle_group_check_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::HouseRules(request) => {
+			let response = handle_house_rules_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::History(request) => {
+			let response = handle_history_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::Stats(request) => {
+			let response = handle_stats_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::Help(request) => {
+			let response = HelpResponse {
+				start: request.start,
+			};
+			Some((request.source, response.to_string().into()))
+		}
+		BotCommand::RegisterDm(request) => {
+			let response = handle_register_dm_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
+		}
+		BotCommand::Refresh(request) => {
+			let response = handle_refresh_request(context, &request).await;
+			Some((request.source, response_to_reply(response)))
 		}
 		BotCommand::Unknown => None,
-		BotCommand::Error { source, error } => Some((source, error)),
+		BotCommand::Error { source, error } => Some((source, error.into())),
 	};
 
-	if let Some((source, message)) = response {
-		source.respond(token, &message).await;
+	if let Some((source, reply)) = response {
+		source.respond(token, &reply).await;
 	}
 }
 
@@ -268,12 +2874,22 @@ fn health() -> &'static str {
 	"OK"
 }
 
-#[post(
-	"/telegram/update/<token>",
-	format = "application/json",
-	data = "<update>"
-)]
-async fn telegram_update<'a>(token: String, update: Json<Update>, context: State<'_, Context>) {
+/// Telegram is the only one who knows the secret token, so requests without it are rejected
+#[post("/telegram/update/<bot_id>", data = "<update>")]
+async fn telegram_update(
+	bot_id: String,
+	_from_telegram: FromTelegram,
+	update: Json<Update>,
+	webhook: State<'_, Webhook>,
+	context: State<'_, Context>,
+) -> Status {
+	let token = match webhook.token(&bot_id) {
+		Some(token) => token.to_string(),
+		None => {
+			println!("Rejected an update for unknown bot {}", bot_id);
+			return Status::NotFound;
+		}
+	};
 	let update = update.0;
 
 	println!("Received update: {:?}", update);
@@ -284,35 +2900,233 @@ async fn telegram_update<'a>(token: String, update: Json<Update>, context: State
 		handle_update(&context, &token, update).await;
 	});
 	println!("success.");
+
+	Status::Ok
 }
 
 #[derive(Clone, Debug)]
 struct Context {
 	redis: Redis,
-	headless: Headless,
+	character_source: Arc<dyn CharacterSource>,
+	/// How long parsed character sheets stay in Redis, in seconds
+	cache_ttl: usize,
+	/// Usernames of the bots by token, fetched on the first update
+	bot_usernames: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl Context {
+	async fn bot_username(&self, token: &str) -> Option<String> {
+		if let Some(username) = self.bot_usernames.lock().unwrap().get(token) {
+			return Some(username.clone());
+		}
+		match telegram::get_me(token).await {
+			Ok(username) => {
+				let mut usernames = self.bot_usernames.lock().unwrap();
+				usernames.insert(token.to_string(), username.clone());
+				Some(username)
+			}
+			Err(err) => {
+				println!("Failed to get the bot's username: {}", err);
+				None
+			}
+		}
+	}
+}
+
+/// Character sheets are cached for a day unless LIGMIR_CACHE_TTL says otherwise
+const DEFAULT_CACHE_TTL: usize = 24 * 60 * 60;
+
+/// Reads characters from the character service if LIGMIR_CHARACTER_SERVICE_URL is set,
+/// otherwise scrapes them with the headless browser
+fn character_source() -> Arc<dyn CharacterSource> {
+	match env::var("LIGMIR_CHARACTER_SERVICE_URL") {
+		Ok(url) => Arc::new(
+			CharacterService::new(&url).expect("Cannot parse LIGMIR_CHARACTER_SERVICE_URL"),
+		),
+		Err(_) => Arc::new(Headless {
+			service_url: env::var("LIGMIR_BROWSER_URL").expect("Expected LIGMIR_BROWSER_URL"),
+			timeout: env::var("LIGMIR_BROWSER_TIMEOUT")
+				.expect("Expected LIGMIR_BROWSER_TIMEOUT")
+				.parse()
+				.expect("Cannot parse LIGMIR_BROWSER_TIMEOUT"),
+		}),
+	}
+}
+
+/// Tokens of the bots this server runs, from the comma-separated LIGMIR_TELEGRAM_TOKENS
+fn telegram_tokens() -> Vec<String> {
+	env::var("LIGMIR_TELEGRAM_TOKENS")
+		.unwrap_or_default()
+		.split(',')
+		.map(str::trim)
+		.filter(|token| !token.is_empty())
+		.map(str::to_string)
+		.collect()
+}
+
+/// Fills the command menu of Telegram clients from the same list as /help
+async fn register_commands(token: String) {
+	let commands: Vec<MenuCommand> = COMMANDS
+		.iter()
+		.map(|(command, description)| MenuCommand {
+			command,
+			description,
+		})
+		.collect();
+	telegram::set_my_commands(&token, &commands).await;
+}
+
+/// How long a getUpdates request waits for new updates, in seconds
+const POLLING_TIMEOUT: u64 = 30;
+
+/// Feeds updates from long polling into the same handler as the webhook
+async fn poll_updates(context: Context, token: String) {
+	telegram::delete_webhook(&token).await;
+
+	let mut offset = None;
+	loop {
+		let updates = match telegram::get_updates(&token, offset, POLLING_TIMEOUT).await {
+			Ok(updates) => updates,
+			Err(err) => {
+				println!("Failed to get updates: {}", err);
+				tokio::time::sleep(Duration::from_secs(5)).await;
+				continue;
+			}
+		};
+		for (id, update) in updates {
+			offset = Some(id + 1);
+			let update = match update {
+				Some(update) => update,
+				None => {
+					println!("Skipping update {} that cannot be parsed", id);
+					continue;
+				}
+			};
+			println!("Received update: {:?}", update);
+			let context = context.clone();
+			let token = token.clone();
+			tokio::spawn(async move {
+				handle_update(&context, &token, update).await;
+			});
+		}
+	}
+}
+
+/// The bot gets updates through the webhook unless LIGMIR_UPDATE_MODE is "polling"
+fn use_polling() -> bool {
+	match env::var("LIGMIR_UPDATE_MODE").as_deref() {
+		Ok("polling") => true,
+		Ok("webhook") | Err(_) => false,
+		Ok(mode) => panic!("Unknown LIGMIR_UPDATE_MODE {}", mode),
+	}
+}
+
+/// Tells Telegram to send the bot's updates to LIGMIR_WEBHOOK_URL along with the secret
+async fn register_webhook(token: String, base_url: String, secret: String) {
+	let url = format!(
+		"{}/telegram/update/{}",
+		base_url.trim_end_matches('/'),
+		webhook::bot_id(&token)
+	);
+	telegram::set_webhook(&token, &url, &secret).await;
 }
 
 #[launch]
 fn rocket() -> Rocket {
-	rocket::ignite()
-		.manage(Context {
-			redis: Redis::open(env::var("LIGMIR_REDIS_URL").expect("Expected LIGMIR_REDIS_URL"))
-				.expect("Failed to initialize Redis client"),
-			headless: Headless {
-				service_url: env::var("LIGMIR_BROWSER_URL").expect("Expected LIGMIR_BROWSER_URL"),
-				timeout: env::var("LIGMIR_BROWSER_TIMEOUT")
-					.expect("Expected LIGMIR_BROWSER_TIMEOUT")
-					.parse()
-					.expect("Cannot parse LIGMIR_BROWSER_TIMEOUT"),
-			},
-		})
-		.mount("/", routes![health, telegram_update])
+	let cache_ttl = env::var("LIGMIR_CACHE_TTL")
+		.map(|ttl| ttl.parse().expect("Cannot parse LIGMIR_CACHE_TTL"))
+		.unwrap_or(DEFAULT_CACHE_TTL);
+	// Redis rejects SETEX with an expiry of zero
+	assert!(cache_ttl > 0, "LIGMIR_CACHE_TTL must be positive");
+
+	let context = Context {
+		redis: Redis::open(env::var("LIGMIR_REDIS_URL").expect("Expected LIGMIR_REDIS_URL"))
+			.expect("Failed to initialize Redis client"),
+		character_source: character_source(),
+		cache_ttl,
+		bot_usernames: Arc::default(),
+	};
+
+	let tokens = telegram_tokens();
+	assert!(!tokens.is_empty(), "Expected LIGMIR_TELEGRAM_TOKENS");
+	for token in &tokens {
+		tokio::spawn(register_commands(token.clone()));
+	}
+
+	let rocket = rocket::ignite().manage(context.clone());
+
+	// Polling needs no public endpoint, so only the health check is served
+	if use_polling() {
+		for token in tokens {
+			tokio::spawn(poll_updates(context.clone(), token));
+		}
+		rocket.mount("/", routes![health])
+	} else {
+		// Webhooks registered before the secret was introduced would be rejected,
+		// so the bot always registers its own webhook on startup
+		let secret = env::var("LIGMIR_WEBHOOK_SECRET").expect("Expected LIGMIR_WEBHOOK_SECRET");
+		assert!(
+			webhook::is_valid_secret(&secret),
+			"LIGMIR_WEBHOOK_SECRET must be 1 to 256 letters, digits, underscores or hyphens"
+		);
+		let url = env::var("LIGMIR_WEBHOOK_URL").expect("Expected LIGMIR_WEBHOOK_URL");
+		for token in &tokens {
+			tokio::spawn(register_webhook(token.clone(), url.clone(), secret.clone()));
+		}
+		rocket
+			.manage(Webhook::new(&tokens, secret))
+			.mount("/", routes![health, telegram_update])
+	}
 }
 
 #[cfg(test)]
 mod tests {
-	use super::{CharacterId, SkillCheckResponse};
-	use std::convert::TryFrom;
+	use super::{
+		apply_conditions,
+		character_sheet::{AbilityScore, CharacterSheet, CharacterSource},
+		command::MissingArguments,
+		conditions::{Condition, Conditions},
+		dice::{Die, Roll, RollMode, TermRoll},
+		fetch_character_sheet, find_ability, parse_command, parse_dm_args, parse_history_args,
+		parse_initiative_action, parse_stats_period, response_to_reply, roll_check, split_dc,
+		split_hidden, split_roll_mode, AttackResponse, BotCommand, CharacterBinding, CharacterId,
+		CheckKind, CheckRequest, Command, GroupCheckResponse, GroupCheckRoll, HitPoints,
+		HitPointsAction, HitPointsResponse, InitiativeAction, ListCharactersResponse,
+		NoAttacksError, NoCharacterError, RequestSource, RollResponse, SkillCheckResponse,
+		StatsPeriod, COMMANDS,
+	};
+	use anyhow::anyhow;
+	use rocket::async_trait;
+	use std::{collections::HashMap, convert::TryFrom};
+	use telegram_bot::{ChatId, MessageId, UserId};
+
+	/// Serves a single character without going to D&D Beyond
+	#[derive(Debug)]
+	struct FakeCharacterSource;
+
+	#[async_trait]
+	impl CharacterSource for FakeCharacterSource {
+		async fn fetch(&self, character_id: CharacterId) -> anyhow::Result<CharacterSheet> {
+			if character_id != CharacterId(36535842) {
+				return Err(anyhow!("Character {:?} not found", character_id));
+			}
+			let skills = vec![("Stealth", 7), ("Arcana", 1), ("Athletics", -1)];
+			Ok(CharacterSheet {
+				name: "Shadowheart".to_string(),
+				skills: skills
+					.into_iter()
+					.map(|(name, modifier)| (name.to_string(), modifier))
+					.collect(),
+				saving_throws: vec![("Dexterity".to_string(), 6)].into_iter().collect(),
+				abilities: vec![("Dexterity".to_string(), AbilityScore::new(18))]
+					.into_iter()
+					.collect(),
+				initiative: 4,
+				max_hit_points: 10,
+				attacks: Vec::new(),
+			})
+		}
+	}
 
 	#[test]
 	fn parse_character_id_from_str() {
@@ -323,9 +3137,15 @@ mod tests {
 	#[test]
 	fn print_skill_check() {
 		let skill_check = SkillCheckResponse {
+			kind: CheckKind::AbilityCheck,
 			skill: "Arcana".to_string(),
 			modifier: 3,
 			d20: 12,
+			roll_mode: RollMode::Normal,
+			discarded_d20: None,
+			condition: None,
+			dc: None,
+			critical_checks: false,
 		};
 		assert_eq!(skill_check.format(), "Arcana check: 3💪+12🎲 = 15");
 	}
@@ -333,10 +3153,557 @@ mod tests {
 	#[test]
 	fn print_skill_check_negative() {
 		let skill_check = SkillCheckResponse {
+			kind: CheckKind::AbilityCheck,
 			skill: "Arcana".to_string(),
 			modifier: -2,
 			d20: 12,
+			roll_mode: RollMode::Normal,
+			discarded_d20: None,
+			condition: None,
+			dc: None,
+			critical_checks: false,
 		};
 		assert_eq!(skill_check.format(), "Arcana check: -2💪+12🎲 = 10");
 	}
+
+	#[test]
+	fn print_roll() {
+		let roll = RollResponse {
+			expression: "1d20-2".parse().unwrap(),
+			roll: Roll {
+				terms: vec![
+					TermRoll::Dice {
+						dice: vec![Die {
+							value: 14,
+							kept: true,
+						}],
+						negative: false,
+					},
+					TermRoll::Constant(-2),
+				],
+			},
+		};
+		assert_eq!(roll.format(), "1d20-2: [14]🎲 - 2 = 12");
+	}
+
+	#[test]
+	fn print_skill_check_with_advantage() {
+		let skill_check = SkillCheckResponse {
+			kind: CheckKind::AbilityCheck,
+			skill: "Stealth".to_string(),
+			modifier: 5,
+			d20: 14,
+			roll_mode: RollMode::Advantage,
+			discarded_d20: Some(3),
+			condition: None,
+			dc: None,
+			critical_checks: false,
+		};
+		assert_eq!(
+			skill_check.format(),
+			"Stealth check with advantage: 5💪+[14, 3\u{336}]🎲 = 19"
+		);
+	}
+
+	#[test]
+	fn print_skill_check_with_condition() {
+		let (roll_mode, condition) =
+			apply_conditions(RollMode::Normal, None, Some(Condition::Poisoned));
+		let skill_check = SkillCheckResponse {
+			kind: CheckKind::AbilityCheck,
+			skill: "Stealth".to_string(),
+			modifier: 5,
+			d20: 3,
+			roll_mode,
+			discarded_d20: Some(14),
+			condition,
+			dc: None,
+			critical_checks: false,
+		};
+		assert_eq!(
+			skill_check.format(),
+			"Stealth check with disadvantage (poisoned): 5💪+[3, 1\u{336}4\u{336}]🎲 = 8"
+		);
+
+		// advantage and disadvantage cancel out
+		let (roll_mode, _) = apply_conditions(RollMode::Advantage, None, Some(Condition::Poisoned));
+		assert_eq!(roll_mode, RollMode::Normal);
+	}
+
+	#[test]
+	fn print_skill_check_against_dc() {
+		let mut skill_check = SkillCheckResponse {
+			kind: CheckKind::AbilityCheck,
+			skill: "Athletics".to_string(),
+			modifier: 5,
+			d20: 13,
+			roll_mode: RollMode::Normal,
+			discarded_d20: None,
+			condition: None,
+			dc: Some(15),
+			critical_checks: false,
+		};
+		assert_eq!(
+			skill_check.format(),
+			"Athletics check: 5💪+13🎲 = 18 vs DC 15 ✅ +3"
+		);
+
+		skill_check.d20 = 7;
+		assert_eq!(
+			skill_check.format(),
+			"Athletics check: 5💪+7🎲 = 12 vs DC 15 ❌ -3"
+		);
+	}
+
+	#[test]
+	fn print_natural_twenty() {
+		let mut skill_check = SkillCheckResponse {
+			kind: CheckKind::AbilityCheck,
+			skill: "Persuasion".to_string(),
+			modifier: -1,
+			d20: 20,
+			roll_mode: RollMode::Normal,
+			discarded_d20: None,
+			condition: None,
+			dc: Some(25),
+			critical_checks: false,
+		};
+		assert_eq!(
+			skill_check.format(),
+			"Persuasion check: -1💪+20🎲 = 19, natural 20! 🌟 vs DC 25 ❌ -6"
+		);
+
+		skill_check.critical_checks = true;
+		assert_eq!(
+			skill_check.format(),
+			"Persuasion check: -1💪+20🎲 = 19, critical success! 🌟 vs DC 25 ✅ automatic success"
+		);
+
+		skill_check.kind = CheckKind::SavingThrow;
+		assert_eq!(
+			skill_check.format(),
+			"Persuasion saving throw: -1💪+20🎲 = 19, natural 20! 🌟 vs DC 25 ❌ -6"
+		);
+	}
+
+	#[test]
+	fn parse_skill_check_advantage() {
+		assert_eq!(
+			split_roll_mode("stealth adv"),
+			("stealth".to_string(), RollMode::Advantage)
+		);
+		assert_eq!(
+			split_roll_mode("sleight of hand dis"),
+			("sleight of hand".to_string(), RollMode::Disadvantage)
+		);
+		assert_eq!(
+			split_roll_mode("adv stealth"),
+			("stealth".to_string(), RollMode::Advantage)
+		);
+		assert_eq!(
+			split_roll_mode("arcana"),
+			("arcana".to_string(), RollMode::Normal)
+		);
+		assert_eq!(
+			split_roll_mode("adv"),
+			("".to_string(), RollMode::Advantage)
+		);
+	}
+
+	#[test]
+	fn parse_hidden_skill_check() {
+		assert_eq!(
+			split_hidden(" insight adv hidden"),
+			("insight adv".to_string(), true)
+		);
+		assert_eq!(
+			split_hidden("insight hidden adv"),
+			("insight adv".to_string(), true)
+		);
+		assert_eq!(
+			split_hidden("HIDDEN sleight of hand"),
+			("sleight of hand".to_string(), true)
+		);
+		assert_eq!(split_hidden(" hidden"), ("".to_string(), true));
+		assert_eq!(
+			split_hidden(" perception"),
+			("perception".to_string(), false)
+		);
+
+		for args in &[
+			"insight hidden adv",
+			"insight adv hidden",
+			"hidden insight adv",
+			"perception hidden dc15",
+		] {
+			let command = Command {
+				name: "skill".to_string(),
+				bot: None,
+				args,
+			};
+			match parse_command(source(), &command, None).unwrap() {
+				BotCommand::Check(request) => {
+					assert!(request.hidden, "/skill {}", args);
+					assert_eq!(request.kind, CheckKind::SkillCheck);
+					assert!(!request.query.contains("hidden"), "/skill {}", args);
+				}
+				_ => panic!("/skill {} is not a skill check", args),
+			}
+		}
+	}
+
+	#[test]
+	fn print_saving_throw() {
+		let saving_throw = SkillCheckResponse {
+			kind: CheckKind::SavingThrow,
+			skill: "Dexterity".to_string(),
+			modifier: 4,
+			d20: 9,
+			roll_mode: RollMode::Normal,
+			discarded_d20: None,
+			condition: None,
+			dc: None,
+			critical_checks: false,
+		};
+		assert_eq!(
+			saving_throw.format(),
+			"Dexterity saving throw: 4💪+9🎲 = 13"
+		);
+	}
+
+	#[test]
+	fn find_ability_by_abbreviation() {
+		let saving_throws: HashMap<String, i32> = vec![
+			("Strength".to_string(), 1),
+			("Dexterity".to_string(), 4),
+			("Wisdom".to_string(), 2),
+		]
+		.into_iter()
+		.collect();
+		assert_eq!(
+			find_ability(saving_throws.clone(), "dex"),
+			Some(("Dexterity".to_string(), 4))
+		);
+		assert_eq!(
+			find_ability(saving_throws, "wisdom"),
+			Some(("Wisdom".to_string(), 2))
+		);
+	}
+
+	#[test]
+	fn print_critical_attack() {
+		let attack = AttackResponse {
+			attack: "Longsword".to_string(),
+			to_hit: Some(5),
+			d20: 20,
+			roll_mode: RollMode::Normal,
+			discarded_d20: None,
+			condition: None,
+			fumble: None,
+			damage: Some((
+				"2d8+3".parse().unwrap(),
+				Roll {
+					terms: vec![
+						TermRoll::Dice {
+							dice: vec![
+								Die {
+									value: 6,
+									kept: true,
+								},
+								Die {
+									value: 2,
+									kept: true,
+								},
+							],
+							negative: false,
+						},
+						TermRoll::Constant(3),
+					],
+				},
+			)),
+			damage_type: Some("Slashing".to_string()),
+		};
+		assert_eq!(
+			attack.format(),
+			"Longsword attack: 5💪+20🎲 = 25, critical hit! 🌟\nDamage (2d8+3 slashing): [6, 2]🎲 + 3 = 11"
+		);
+	}
+
+	#[test]
+	fn print_character_list() {
+		let characters = ListCharactersResponse {
+			characters: vec![
+				("Gimli".to_string(), CharacterId(1)),
+				("Legolas".to_string(), CharacterId(2)),
+			],
+			active: Some(CharacterId(2)),
+		};
+		assert_eq!(
+			characters.format(),
+			"Your characters:\nGimli\nLegolas (playing)"
+		);
+	}
+
+	#[test]
+	fn offer_demo_character_when_none_is_set() {
+		let reply = response_to_reply::<String>(Err(NoCharacterError.into()));
+		assert!(reply.text.contains("/character"));
+		assert!(reply.keyboard.is_some());
+	}
+
+	#[test]
+	fn explain_missing_attacks() {
+		let reply = response_to_reply::<String>(Err(NoAttacksError::NotRead.into()));
+		assert!(reply.text.contains("/roll"));
+		let reply = response_to_reply::<String>(Err(NoAttacksError::Empty.into()));
+		assert_eq!(reply.text, "The character has no attacks.");
+	}
+
+	#[test]
+	fn parse_history() {
+		assert_eq!(parse_history_args("").unwrap(), (10, None));
+		assert_eq!(
+			parse_history_args(" 5 @anna").unwrap(),
+			(5, Some("@anna".to_string()))
+		);
+		assert_eq!(parse_history_args(" 1000").unwrap(), (50, None));
+		assert!(parse_history_args(" yesterday").is_err());
+	}
+
+	fn source() -> RequestSource {
+		RequestSource {
+			chat_id: ChatId::new(1),
+			message_id: MessageId::new(1),
+			user_id: UserId::new(1),
+			user_name: "Anna".to_string(),
+			username: None,
+		}
+	}
+
+	#[test]
+	fn private_chats_set_default_character() {
+		assert_eq!(CharacterBinding::of(&source()), CharacterBinding::Default);
+		let group = RequestSource {
+			chat_id: ChatId::new(-100),
+			..source()
+		};
+		assert_eq!(
+			CharacterBinding::of(&group),
+			CharacterBinding::Chat(ChatId::new(-100))
+		);
+	}
+
+	#[test]
+	fn require_skill_besides_options() {
+		for args in &["dc15", "hidden", "adv dc15 hidden"] {
+			let command = Command {
+				name: "skill".to_string(),
+				bot: None,
+				args,
+			};
+			let error = parse_command(source(), &command, None).err().unwrap();
+			assert!(error.is::<MissingArguments>(), "/skill {}", args);
+		}
+	}
+
+	#[test]
+	fn help_lists_known_commands() {
+		let source = source();
+		for (name, _) in COMMANDS.iter() {
+			let command = Command {
+				name: name.to_string(),
+				bot: None,
+				args: "",
+			};
+			let bot_command = parse_command(source.clone(), &command, None);
+			assert!(
+				!matches!(bot_command, Ok(BotCommand::Unknown)),
+				"/{} is not a command",
+				name
+			);
+		}
+	}
+
+	#[test]
+	fn parse_stats() {
+		assert_eq!(parse_stats_period("").unwrap(), StatsPeriod::Campaign);
+		assert_eq!(
+			parse_stats_period(" Session").unwrap(),
+			StatsPeriod::Session
+		);
+		assert!(parse_stats_period(" forever").is_err());
+	}
+
+	#[test]
+	fn parse_dm() {
+		assert!(!parse_dm_args("").unwrap());
+		assert!(parse_dm_args(" OFF").unwrap());
+		assert!(parse_dm_args(" @anna").is_err());
+	}
+
+	#[test]
+	fn record_attack_rolls() {
+		let attack = AttackResponse {
+			attack: "Longsword".to_string(),
+			to_hit: Some(5),
+			d20: 14,
+			roll_mode: RollMode::Normal,
+			discarded_d20: None,
+			condition: None,
+			damage: None,
+			damage_type: None,
+			fumble: None,
+		};
+		let records = attack.roll_records();
+		assert_eq!(records.len(), 1);
+		assert_eq!(records[0].roll, "Longsword attack");
+		assert_eq!(records[0].dice, "5💪+14🎲 = 19");
+		assert_eq!(records[0].d20, Some(14));
+		assert_eq!(records[0].total, 19);
+	}
+
+	#[test]
+	fn parse_initiative_actions() {
+		assert!(matches!(
+			parse_initiative_action(""),
+			Ok(InitiativeAction::Join(RollMode::Normal))
+		));
+		assert!(matches!(
+			parse_initiative_action(" adv"),
+			Ok(InitiativeAction::Join(RollMode::Advantage))
+		));
+		match parse_initiative_action(" add Goblin Boss +4") {
+			Ok(InitiativeAction::AddMonster { name, bonus }) => {
+				assert_eq!(name, "Goblin Boss");
+				assert_eq!(bonus, 4);
+			}
+			_ => panic!("Expected a monster"),
+		}
+		match parse_initiative_action(" add Ogre") {
+			Ok(InitiativeAction::AddMonster { name, bonus }) => {
+				assert_eq!(name, "Ogre");
+				assert_eq!(bonus, 0);
+			}
+			_ => panic!("Expected a monster"),
+		}
+		assert!(parse_initiative_action(" add").is_err());
+		assert!(parse_initiative_action(" dance").is_err());
+	}
+
+	#[test]
+	fn print_hit_points() {
+		let mut hit_points = HitPoints::new(12);
+		hit_points.damage(5);
+		let response = HitPointsResponse {
+			name: "Gimli".to_string(),
+			action: HitPointsAction::Damage(5),
+			hit_points,
+		};
+		assert_eq!(response.format(), "Gimli takes 5 damage: 7/12 HP");
+
+		hit_points.damage(10);
+		let response = HitPointsResponse {
+			hit_points,
+			..response
+		};
+		assert_eq!(
+			response.format(),
+			"Gimli drops to 0 HP and falls unconscious! 💀"
+		);
+	}
+
+	#[test]
+	fn parse_dc() {
+		let split = |args| {
+			let (args, dc) = split_dc(args);
+			let (args, roll_mode) = split_roll_mode(&args);
+			(args, dc, roll_mode)
+		};
+		let athletics = ("athletics".to_string(), Some(15), RollMode::Advantage);
+		assert_eq!(split(" athletics dc15 adv"), athletics);
+		assert_eq!(split("athletics adv dc15"), athletics);
+		assert_eq!(split("adv DC15 athletics"), athletics);
+		assert_eq!(
+			split_dc(" stealth adv DC12"),
+			("stealth adv".to_string(), Some(12))
+		);
+		assert_eq!(split_dc(" dc10"), ("".to_string(), Some(10)));
+		assert_eq!(
+			split_dc(" sleight of hand"),
+			("sleight of hand".to_string(), None)
+		);
+	}
+
+	#[test]
+	fn print_group_check() {
+		let roll = |name: &str, modifier, d20| GroupCheckRoll {
+			name: name.to_string(),
+			modifier,
+			d20,
+			roll_mode: RollMode::Normal,
+			discarded_d20: None,
+			condition: None,
+		};
+		let group_check = GroupCheckResponse {
+			skill: "Stealth".to_string(),
+			dc: Some(12),
+			rolls: vec![roll("Legolas", 7, 11), roll("Gimli", -1, 9)],
+			critical_checks: false,
+		};
+		assert_eq!(
+			group_check.format(),
+			"Stealth group check, DC 12:\n\
+			Legolas: 7💪+11🎲 = 18 ✅ +6\n\
+			Gimli: -1💪+9🎲 = 8 ❌ -4\n\
+			1 of 2 succeeded, the group succeeds! ✅"
+		);
+	}
+
+	#[rocket::async_test]
+	async fn roll_checks_from_fetched_character() {
+		let error = fetch_character_sheet(&FakeCharacterSource, CharacterId(1))
+			.await
+			.err()
+			.unwrap();
+		assert!(error
+			.to_string()
+			.starts_with("Failed to download character sheet"));
+
+		let character_sheet = fetch_character_sheet(&FakeCharacterSource, CharacterId(36535842))
+			.await
+			.unwrap();
+		let check = |kind, query: &str| CheckRequest {
+			source: source(),
+			kind,
+			query: query.to_string(),
+			roll_mode: RollMode::Normal,
+			dc: Some(10),
+			hidden: false,
+		};
+		let conditions = Conditions::default();
+
+		let request = check(CheckKind::SkillCheck, "Stelth");
+		let response = roll_check(&request, &character_sheet, &conditions, false).unwrap();
+		assert_eq!(response.skill, "Stealth");
+		assert_eq!(response.modifier, 7);
+		assert_eq!(response.dc, Some(10));
+
+		let request = check(CheckKind::AbilityCheck, "dex");
+		let response = roll_check(&request, &character_sheet, &conditions, false).unwrap();
+		assert_eq!(
+			(response.skill.as_str(), response.modifier),
+			("Dexterity", 4)
+		);
+
+		let request = check(CheckKind::SavingThrow, "dex");
+		let mut conditions = Conditions::default();
+		conditions.add(Condition::Restrained);
+		let response = roll_check(&request, &character_sheet, &conditions, false).unwrap();
+		assert_eq!(
+			(response.skill.as_str(), response.modifier),
+			("Dexterity", 6)
+		);
+		assert_eq!(response.roll_mode, RollMode::Disadvantage);
+		assert_eq!(response.condition, Some(Condition::Restrained));
+	}
 }
@@ -0,0 +1,46 @@
+/// Implements `ToRedisArgs` and `FromRedisValue` for a type by storing it as a JSON string
+macro_rules! redis_json {
+	($type:ty) => {
+		impl redis::ToRedisArgs for $type {
+			fn write_redis_args<W>(&self, out: &mut W)
+			where
+				W: ?Sized + redis::RedisWrite,
+			{
+				let json = serde_json::to_string(self)
+					.expect(concat!(stringify!($type), " is always serializable"));
+				redis::ToRedisArgs::write_redis_args(&json, out)
+			}
+		}
+
+		impl redis::FromRedisValue for $type {
+			fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Self> {
+				let json: String = redis::FromRedisValue::from_redis_value(v)?;
+				serde_json::from_str(&json).map_err(|_| {
+					(
+						redis::ErrorKind::TypeError,
+						concat!("Cannot deserialize ", stringify!($type)),
+					)
+						.into()
+				})
+			}
+		}
+	};
+}
+
+pub(crate) use redis_json;
+
+#[cfg(test)]
+mod tests {
+	use crate::hit_points::HitPoints;
+	use redis::{FromRedisValue, ToRedisArgs, Value};
+
+	#[test]
+	fn store_as_json() {
+		let hit_points = HitPoints::new(12);
+		let args = hit_points.to_redis_args();
+		assert_eq!(args, vec![br#"{"current":12,"max":12,"temp":0}"#.to_vec()]);
+		let value = Value::Data(args[0].clone());
+		assert_eq!(HitPoints::from_redis_value(&value).unwrap(), hit_points);
+		assert!(HitPoints::from_redis_value(&Value::Data(b"12".to_vec())).is_err());
+	}
+}
@@ -0,0 +1,214 @@
+use crate::history::RollRecord;
+
+/// χ² above this means a fair d20 would roll like this less than 5% of the time (19 degrees of freedom)
+const CHI_SQUARED_CRITICAL: f64 = 30.144;
+
+/// The χ² test needs about 5 rolls of each face to mean anything
+const MIN_ROLLS: u32 = 100;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// How many times each face of the d20 came up
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct D20Stats {
+	pub counts: [u32; 20],
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fairness {
+	TooFewRolls,
+	Fair,
+	Suspicious,
+}
+
+impl D20Stats {
+	pub fn add(&mut self, d20: i32) {
+		if (1..=20).contains(&d20) {
+			self.counts[d20 as usize - 1] += 1;
+		}
+	}
+
+	pub fn rolls(&self) -> u32 {
+		self.counts.iter().sum()
+	}
+
+	/// How many times the face came up
+	pub fn naturals(&self, face: i32) -> u32 {
+		self.counts[face as usize - 1]
+	}
+
+	pub fn average(&self) -> f64 {
+		let sum: u32 = (1..)
+			.zip(self.counts.iter())
+			.map(|(face, count)| face * count)
+			.sum();
+		f64::from(sum) / f64::from(self.rolls().max(1))
+	}
+
+	/// Pearson's χ² statistic against a fair d20
+	pub fn chi_squared(&self) -> f64 {
+		let expected = f64::from(self.rolls()) / 20.0;
+		if expected == 0.0 {
+			return 0.0;
+		}
+		self.counts
+			.iter()
+			.map(|&count| (f64::from(count) - expected).powi(2) / expected)
+			.sum()
+	}
+
+	pub fn fairness(&self) -> Fairness {
+		if self.rolls() < MIN_ROLLS {
+			Fairness::TooFewRolls
+		} else if self.chi_squared() > CHI_SQUARED_CRITICAL {
+			Fairness::Suspicious
+		} else {
+			Fairness::Fair
+		}
+	}
+
+	/// A bar for each face from 1 to 20, such as "▃▅▂█…"
+	pub fn histogram(&self) -> String {
+		let max = self.counts.iter().copied().max().unwrap_or(0).max(1);
+		self.counts
+			.iter()
+			.map(|&count| BARS[(count * (BARS.len() as u32 - 1) / max) as usize])
+			.collect()
+	}
+
+	pub fn format(&self, name: &str) -> String {
+		let fairness = match self.fairness() {
+			Fairness::TooFewRolls => "too few rolls to judge the dice".to_string(),
+			Fairness::Fair => format!("χ² = {:.1}, the dice look fair", self.chi_squared()),
+			Fairness::Suspicious => format!(
+				"χ² = {:.1}, the dice look suspicious 🤨",
+				self.chi_squared()
+			),
+		};
+		format!(
+			"{}: {} d20s, average {:.1}, {} natural 20s, {} natural 1s\n1 {} 20\n{}",
+			name,
+			self.rolls(),
+			self.average(),
+			self.naturals(20),
+			self.naturals(1),
+			self.histogram(),
+			fairness
+		)
+	}
+}
+
+/// d20 statistics of one player
+pub struct PlayerStats {
+	pub name: String,
+	pub d20: D20Stats,
+}
+
+/// Counts every d20 the players rolled, including the ones dropped for advantage or disadvantage.
+/// Hidden rolls are left out so that the statistics don't give them away.
+pub fn player_stats(rolls: &[RollRecord]) -> Vec<PlayerStats> {
+	let mut players: Vec<(i64, PlayerStats)> = Vec::new();
+	for roll in rolls.iter().filter(|roll| !roll.hidden) {
+		let d20s: Vec<i32> = roll
+			.d20
+			.iter()
+			.chain(&roll.discarded_d20)
+			.copied()
+			.collect();
+		if d20s.is_empty() {
+			continue;
+		}
+
+		let index = match players
+			.iter()
+			.position(|(user_id, _)| *user_id == roll.user_id)
+		{
+			Some(index) => index,
+			None => {
+				let stats = PlayerStats {
+					name: roll.name().to_string(),
+					d20: D20Stats::default(),
+				};
+				players.push((roll.user_id, stats));
+				players.len() - 1
+			}
+		};
+		let stats = &mut players[index].1;
+		// Group checks only know the character, so prefer a name from the player's own rolls
+		if !roll.user_name.is_empty() {
+			stats.name = roll.user_name.clone();
+		}
+		for d20 in d20s {
+			stats.d20.add(d20);
+		}
+	}
+
+	let mut players: Vec<PlayerStats> = players.into_iter().map(|(_, stats)| stats).collect();
+	players.sort_by_key(|stats| std::cmp::Reverse(stats.d20.rolls()));
+	players
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{player_stats, D20Stats, Fairness};
+	use crate::history::RollRecord;
+
+	#[test]
+	fn fair_dice() {
+		let mut stats = D20Stats::default();
+		for _ in 0..10 {
+			for d20 in 1..=20 {
+				stats.add(d20);
+			}
+		}
+		assert_eq!(stats.rolls(), 200);
+		assert!((stats.average() - 10.5).abs() < 1e-9);
+		assert_eq!(stats.chi_squared(), 0.0);
+		assert_eq!(stats.fairness(), Fairness::Fair);
+		assert_eq!(stats.histogram(), "█".repeat(20));
+	}
+
+	#[test]
+	fn loaded_dice() {
+		let mut stats = D20Stats::default();
+		for _ in 0..100 {
+			stats.add(1);
+		}
+		assert_eq!(stats.naturals(1), 100);
+		assert!(stats.chi_squared() > 1000.0);
+		assert_eq!(stats.fairness(), Fairness::Suspicious);
+
+		stats.add(20);
+		assert!(stats.histogram().starts_with('█'));
+		assert!(stats.histogram().ends_with('▁'));
+	}
+
+	#[test]
+	fn count_rolls_per_player() {
+		let roll = |user_id, user_name: &str, d20, discarded_d20| RollRecord {
+			user_id,
+			user_name: user_name.to_string(),
+			character: Some("Gimli".to_string()),
+			d20,
+			discarded_d20,
+			..RollRecord::default()
+		};
+		let rolls = vec![
+			roll(1, "", Some(3), None),
+			roll(2, "Boris", Some(12), Some(7)),
+			roll(1, "Anna", Some(20), Some(1)),
+			roll(1, "Anna", None, None),
+			RollRecord {
+				hidden: true,
+				..roll(2, "Boris", Some(5), None)
+			},
+		];
+		let players = player_stats(&rolls);
+		assert_eq!(players.len(), 2);
+		assert_eq!(players[0].name, "Anna");
+		assert_eq!(players[0].d20.rolls(), 3);
+		assert_eq!(players[0].d20.naturals(20), 1);
+		assert_eq!(players[1].name, "Boris");
+		assert_eq!(players[1].d20.rolls(), 2);
+	}
+}
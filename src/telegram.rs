@@ -1,39 +1,243 @@
+use anyhow::anyhow;
 use rocket::futures::TryFutureExt;
-use serde::Serialize;
-use telegram_bot::{ChatId, MessageId};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use telegram_bot::{CallbackQueryId, ChatId, InlineKeyboardMarkup, MessageId, Update};
 use url::Url;
 
 #[derive(Serialize)]
 struct SendMessage {
 	chat_id: ChatId,
 	text: String,
-	reply_to_message_id: MessageId,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	reply_to_message_id: Option<MessageId>,
+	/// JSON-encoded keyboard, since query strings cannot hold nested objects
+	#[serde(skip_serializing_if = "Option::is_none")]
+	reply_markup: Option<String>,
 }
 
-pub async fn send_message(token: &str, chat_id: ChatId, message: &str, reply_to: MessageId) {
-	let query = serde_urlencoded::to_string(SendMessage {
+#[derive(Serialize)]
+struct AnswerCallbackQuery<'a> {
+	callback_query_id: &'a CallbackQueryId,
+}
+
+/// A command in the menu that clients show next to the message field
+#[derive(Serialize)]
+pub struct MenuCommand<'a> {
+	pub command: &'a str,
+	pub description: &'a str,
+}
+
+#[derive(Serialize)]
+struct SetMyCommands {
+	/// JSON-encoded list of commands
+	commands: String,
+}
+
+#[derive(Serialize)]
+struct SetWebhook<'a> {
+	url: &'a str,
+	/// Telegram sends it back in a header with every update
+	secret_token: &'a str,
+}
+
+#[derive(Serialize)]
+struct GetUpdates {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	offset: Option<i64>,
+	/// How long to wait for updates, in seconds
+	timeout: u64,
+}
+
+#[derive(Deserialize)]
+struct BotUser {
+	username: String,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+	ok: bool,
+	result: Option<T>,
+	description: Option<String>,
+}
+
+/// Calls a Bot API method with the parameters passed in the query string
+async fn call_method<T, R>(token: &str, method: &str, params: T) -> anyhow::Result<R>
+where
+	T: Serialize,
+	R: DeserializeOwned,
+{
+	let query = serde_urlencoded::to_string(params)?;
+
+	let mut url: Url = format!("https://api.telegram.org/bot{}/{}", token, method).parse()?;
+	url.set_query(Some(&query));
+
+	let response = reqwest::get(url)
+		.and_then(|response| response.text())
+		.await?;
+	parse_response(&response)
+}
+
+/// Telegram answers with "ok": false and a description when it refuses a request
+fn parse_response<R: DeserializeOwned>(response: &str) -> anyhow::Result<R> {
+	let response: ApiResponse<R> = serde_json::from_str(response)?;
+	match response.result {
+		Some(result) if response.ok => Ok(result),
+		_ => Err(anyhow!(
+			"Telegram refused the request: {}",
+			response.description.unwrap_or_default()
+		)),
+	}
+}
+
+/// Returns whether the message was sent. Telegram refuses to send private messages
+/// to users who haven't started a chat with the bot.
+pub async fn send_message(
+	token: &str,
+	chat_id: ChatId,
+	message: &str,
+	reply_to: Option<MessageId>,
+	keyboard: Option<&InlineKeyboardMarkup>,
+) -> bool {
+	let reply_markup = match keyboard.map(serde_json::to_string).transpose() {
+		Ok(reply_markup) => reply_markup,
+		Err(err) => {
+			println!("Failed to serialize keyboard: {}", err);
+			return false;
+		}
+	};
+	let params = SendMessage {
 		chat_id,
 		text: message.to_string(),
 		reply_to_message_id: reply_to,
-	});
-	let query = match query {
-		Ok(query) => query,
+		reply_markup,
+	};
+
+	let response: anyhow::Result<serde_json::Value> =
+		call_method(token, "sendMessage", params).await;
+	if let Err(err) = &response {
+		println!(
+			r#"Failed to send message "{}" to chat {}: {}"#,
+			message, chat_id, err
+		);
+	}
+	response.is_ok()
+}
+
+/// Stops the loading animation on the inline button that was pressed
+pub async fn answer_callback_query(token: &str, callback_query_id: &CallbackQueryId) {
+	let params = AnswerCallbackQuery { callback_query_id };
+
+	let response: anyhow::Result<serde_json::Value> =
+		call_method(token, "answerCallbackQuery", params).await;
+	if let Err(err) = response {
+		println!(
+			"Failed to answer callback query {:?}: {}",
+			callback_query_id, err
+		);
+	}
+}
+
+/// Replaces the bot's command menu
+pub async fn set_my_commands(token: &str, commands: &[MenuCommand<'_>]) {
+	let commands = match serde_json::to_string(commands) {
+		Ok(commands) => commands,
 		Err(err) => {
-			println!("Failed to serialize message: {}", err);
+			println!("Failed to serialize commands: {}", err);
 			return;
 		}
 	};
+	let params = SetMyCommands { commands };
 
-	let mut url: Url = format!("https://api.telegram.org/bot{}/sendMessage", token)
-		.parse()
-		.unwrap();
-	url.set_query(Some(&query));
+	let response: anyhow::Result<serde_json::Value> =
+		call_method(token, "setMyCommands", params).await;
+	if let Err(err) = response {
+		println!("Failed to set the bot's commands: {}", err);
+	}
+}
+
+/// The username of the bot, which users add to commands meant for it, as in "/roll@ligmir_bot"
+pub async fn get_me(token: &str) -> anyhow::Result<String> {
+	let me: BotUser = call_method(token, "getMe", ()).await?;
+	Ok(me.username)
+}
 
-	let response = reqwest::get(url).and_then(|response| response.text()).await;
+/// Points Telegram at the webhook
+pub async fn set_webhook(token: &str, url: &str, secret_token: &str) {
+	let params = SetWebhook { url, secret_token };
+
+	let response: anyhow::Result<serde_json::Value> =
+		call_method(token, "setWebhook", params).await;
 	if let Err(err) = response {
-		println!(
-			r#"Failed to send message "{}" to user {} in chat {}: {}"#,
-			message, reply_to, chat_id, err
-		);
+		println!("Failed to set the webhook to {}: {}", url, err);
+	}
+}
+
+/// Long polling needs the webhook to be removed first
+pub async fn delete_webhook(token: &str) {
+	let response: anyhow::Result<serde_json::Value> = call_method(token, "deleteWebhook", ()).await;
+	if let Err(err) = response {
+		println!("Failed to delete the webhook: {}", err);
+	}
+}
+
+/// Waits for updates newer than the offset. Every update comes with its ID, and with `None`
+/// instead of the update if it can't be parsed, so that the caller can skip past it.
+pub async fn get_updates(
+	token: &str,
+	offset: Option<i64>,
+	timeout: u64,
+) -> anyhow::Result<Vec<(i64, Option<Update>)>> {
+	let params = GetUpdates { offset, timeout };
+	let updates = call_method(token, "getUpdates", params).await?;
+	parse_updates(updates)
+}
+
+fn parse_updates(updates: Vec<serde_json::Value>) -> anyhow::Result<Vec<(i64, Option<Update>)>> {
+	updates
+		.into_iter()
+		.map(|update| {
+			let id = update["update_id"]
+				.as_i64()
+				.ok_or_else(|| anyhow!("Update without an ID: {}", update))?;
+			Ok((id, serde_json::from_value(update).ok()))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_response, parse_updates};
+
+	#[test]
+	fn skip_unparseable_updates() {
+		let response = r#"{
+			"ok": true,
+			"result": [
+				{
+					"update_id": 10,
+					"message": {
+						"message_id": 1,
+						"date": 1600000000,
+						"chat": { "id": 42, "type": "private", "first_name": "Anna" },
+						"from": { "id": 42, "is_bot": false, "first_name": "Anna" },
+						"text": "/roll"
+					}
+				},
+				{ "update_id": 11, "message": "garbage" }
+			]
+		}"#;
+		let updates = parse_updates(parse_response(response).unwrap()).unwrap();
+		assert_eq!(updates.len(), 2);
+		assert_eq!(updates[0].0, 10);
+		assert!(updates[0].1.is_some());
+		assert_eq!(updates[1].0, 11);
+		assert!(updates[1].1.is_none());
+	}
+
+	#[test]
+	fn report_api_errors() {
+		let response = r#"{ "ok": false, "error_code": 409, "description": "Conflict" }"#;
+		let error = parse_response::<serde_json::Value>(response).unwrap_err();
+		assert_eq!(error.to_string(), "Telegram refused the request: Conflict");
 	}
 }
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use rocket::{
+	async_trait,
+	http::Status,
+	request::{FromRequest, Outcome, Request},
+	State,
+};
+
+/// The header Telegram puts the secret token from setWebhook into
+const SECRET_TOKEN_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+
+/// A webhook request that carries the secret token, which only Telegram knows.
+/// Other requests are rejected before their body is read.
+pub struct FromTelegram;
+
+#[async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for FromTelegram {
+	type Error = ();
+
+	async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+		let webhook = match request.guard::<State<'_, Webhook>>().await {
+			Outcome::Success(webhook) => webhook,
+			_ => return Outcome::Failure((Status::InternalServerError, ())),
+		};
+		match request.headers().get_one(SECRET_TOKEN_HEADER) {
+			Some(secret) if webhook.accepts(secret) => Outcome::Success(FromTelegram),
+			_ => Outcome::Failure((Status::Unauthorized, ())),
+		}
+	}
+}
+
+/// The bots the webhook accepts updates for
+pub struct Webhook {
+	/// Bot tokens by bot ID, which is the part of the token before the colon
+	tokens: HashMap<String, String>,
+	secret: String,
+}
+
+impl Webhook {
+	pub fn new(tokens: &[String], secret: String) -> Webhook {
+		let tokens = tokens
+			.iter()
+			.map(|token| (bot_id(token).to_string(), token.clone()))
+			.collect();
+		Webhook { tokens, secret }
+	}
+
+	fn accepts(&self, secret: &str) -> bool {
+		constant_time_eq(secret.as_bytes(), self.secret.as_bytes())
+	}
+
+	/// The token of the bot the update was sent to
+	pub fn token(&self, bot_id: &str) -> Option<&str> {
+		self.tokens.get(bot_id).map(String::as_str)
+	}
+}
+
+/// Telegram only accepts secret tokens of 1 to 256 letters, digits, underscores and hyphens
+pub fn is_valid_secret(secret: &str) -> bool {
+	(1..=256).contains(&secret.len())
+		&& secret
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Bot tokens look like "123456:ABC-DEF", where the number is the bot's ID
+pub fn bot_id(token: &str) -> &str {
+	token.split(':').next().unwrap_or_default()
+}
+
+/// Compares secrets without revealing how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{bot_id, is_valid_secret, Webhook};
+
+	#[test]
+	fn accept_only_known_bots_with_the_secret() {
+		let webhook = Webhook::new(&["123:abc".to_string()], "s3cret".to_string());
+		assert_eq!(bot_id("123:abc"), "123");
+		assert!(webhook.accepts("s3cret"));
+		assert!(!webhook.accepts("guess"));
+		assert!(!webhook.accepts(""));
+		assert_eq!(webhook.token("123"), Some("123:abc"));
+		assert_eq!(webhook.token("456"), None);
+	}
+
+	#[test]
+	fn check_secret_format() {
+		assert!(is_valid_secret("s3cret_token-1"));
+		assert!(is_valid_secret(&"a".repeat(256)));
+		assert!(!is_valid_secret(""));
+		assert!(!is_valid_secret(&"a".repeat(257)));
+		assert!(!is_valid_secret("s3cret token"));
+		assert!(!is_valid_secret("s3cret!"));
+	}
+}